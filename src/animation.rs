@@ -0,0 +1,162 @@
+use crate::sdf::Light;
+use bevy::prelude::*;
+
+/// Animates the intensity and colour of a `Light`, on top of the values
+/// it is given. Runs on virtual time, pausing `Time<Virtual>` freezes it.
+///
+/// On an `Emitter` the animation plays on the `Light` the emitter keeps
+/// in sync, the `Emitter` itself is left as is. Writing the light yourself,
+/// or changing the `Emitter`, changes the base the animation works from.
+#[derive(Component, Clone)]
+pub struct LightAnimation {
+    pub mode: AnimationMode,
+    /// lights with different seeds run out of sync, the same seed
+    /// always plays the same animation. Without one the seed is taken
+    /// from the entity, so no two lights play in lockstep.
+    pub seed: Option<u32>,
+    /// intensity and colour the animation scales
+    base: Option<(f32, LinearRgba)>,
+    /// last values written, anything else was changed from outside
+    written: Option<(f32, LinearRgba)>,
+}
+
+impl LightAnimation {
+    pub fn new(mode: AnimationMode) -> Self {
+        Self {
+            mode,
+            seed: None,
+            base: None,
+            written: None,
+        }
+    }
+
+    pub fn with_seed(mut self, seed: u32) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// intensity and colour multiplier at `time` seconds, phases are
+    /// taken in f64 so they stay smooth in long running sessions
+    pub fn sample(&self, seed: u32, time: f64) -> (f32, LinearRgba) {
+        let offset = hash(seed, 0) as f64;
+        match &self.mode {
+            AnimationMode::Flicker { speed, amount } => {
+                let noise = value_noise(seed, time * *speed as f64);
+                (1. - amount * noise, LinearRgba::WHITE)
+            }
+            AnimationMode::Pulse { frequency, min } => {
+                let phase = (time * *frequency as f64 + offset).fract() as f32;
+                let wave = ((phase * std::f32::consts::TAU).sin() + 1.) / 2.;
+                (min + (1. - min) * wave, LinearRgba::WHITE)
+            }
+            AnimationMode::Strobe { frequency, duty } => {
+                let on = ((time * *frequency as f64 + offset).fract() as f32) < *duty;
+                (if on { 1. } else { 0. }, LinearRgba::WHITE)
+            }
+            AnimationMode::Keyframes(keys) => keyframe(keys, time + offset * duration(keys) as f64),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum AnimationMode {
+    /// smooth random dips of up to `amount` of the intensity,
+    /// `speed` is the number of changes per second
+    Flicker { speed: f32, amount: f32 },
+    /// sine wave between `min` and the full intensity
+    Pulse { frequency: f32, min: f32 },
+    /// flashes per second, lit for `duty` of each flash
+    Strobe { frequency: f32, duty: f32 },
+    /// intensity and colour multipliers over time,
+    /// sorted by time and looping after the last key
+    Keyframes(Vec<LightKeyframe>),
+}
+
+#[derive(Clone)]
+pub struct LightKeyframe {
+    /// seconds from the start of the loop
+    pub time: f32,
+    pub intensity: f32,
+    pub color: Color,
+}
+
+pub(crate) fn animate_lights(
+    time: Res<Time>,
+    mut lights: Query<(Entity, &mut Light, &mut LightAnimation)>,
+) {
+    let elapsed = time.elapsed_seconds_f64();
+
+    for (entity, mut light, mut animation) in lights.iter_mut() {
+        let current = (light.intensity, light.color.to_linear());
+        if animation.base.is_none() || animation.written != Some(current) {
+            animation.base = Some(current);
+        }
+
+        let Some((intensity, color)) = animation.base else {
+            continue;
+        };
+
+        let seed = animation.seed.unwrap_or_else(|| entity_seed(entity));
+        let (intensity_factor, color_factor) = animation.sample(seed, elapsed);
+        let color = LinearRgba::from_vec4(color.to_vec4() * color_factor.to_vec4());
+        let animated = (intensity * intensity_factor, color);
+        light.intensity = animated.0;
+        light.color = animated.1.into();
+        animation.written = Some(animated);
+    }
+}
+
+fn duration(keys: &[LightKeyframe]) -> f32 {
+    keys.last().map_or(0., |key| key.time)
+}
+
+fn keyframe(keys: &[LightKeyframe], time: f64) -> (f32, LinearRgba) {
+    let sample = |key: &LightKeyframe| (key.intensity, key.color.to_linear());
+    let (Some(first), Some(last)) = (keys.first(), keys.last()) else {
+        return (1., LinearRgba::WHITE);
+    };
+
+    if last.time <= 0. {
+        return sample(first);
+    }
+
+    let time = time.rem_euclid(last.time as f64) as f32;
+    let next = keys.iter().position(|key| key.time > time).unwrap_or(0);
+    if next == 0 {
+        return sample(first);
+    }
+
+    let (a, b) = (&keys[next - 1], &keys[next]);
+    let t = (time - a.time) / (b.time - a.time).max(f32::EPSILON);
+    (
+        a.intensity.lerp(b.intensity, t),
+        a.color.to_linear().mix(&b.color.to_linear(), t),
+    )
+}
+
+/// smooth 1D noise in 0..1, the same for the same seed
+fn value_noise(seed: u32, x: f64) -> f32 {
+    let cell = x.floor();
+    let t = (x - cell) as f32;
+    let t = t * t * (3. - 2. * t);
+    let a = hash(seed, cell as i64 as u32);
+    let b = hash(seed, (cell as i64 + 1) as u32);
+    a.lerp(b, t)
+}
+
+/// seed of an animation without one
+fn entity_seed(entity: Entity) -> u32 {
+    let bits = entity.to_bits();
+    (bits ^ (bits >> 32)) as u32
+}
+
+/// integer hash to 0..1
+fn hash(seed: u32, n: u32) -> f32 {
+    let mut x = n.wrapping_mul(0x9E37_79B9) ^ seed.wrapping_mul(0x85EB_CA6B);
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7FEB_352D);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846C_A68B);
+    x ^= x >> 16;
+    x as f32 / u32::MAX as f32
+}
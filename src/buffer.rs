@@ -0,0 +1,141 @@
+use bevy::render::{
+    render_resource::{
+        encase::{self, internal::WriteInto},
+        BindingResource, Buffer, BufferDescriptor, BufferUsages, ShaderSize,
+    },
+    renderer::{RenderDevice, RenderQueue},
+};
+use std::ops::Range;
+
+/// A persistent storage buffer of plain `array<T>` with stable slots.
+///
+/// Only slots written since the last upload are sent to the gpu, grouped
+/// into contiguous ranges. The buffer only gets recreated when it grows.
+pub struct SlotBuffer<T> {
+    values: Vec<T>,
+    free: Vec<u32>,
+    dirty: Vec<u32>,
+    buffer: Option<Buffer>,
+    label: &'static str,
+}
+
+impl<T: ShaderSize + WriteInto> SlotBuffer<T> {
+    pub fn new(label: &'static str) -> Self {
+        Self {
+            values: Vec::new(),
+            free: Vec::new(),
+            dirty: Vec::new(),
+            buffer: None,
+            label,
+        }
+    }
+
+    /// slot count including free slots
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn get(&self, slot: u32) -> &T {
+        &self.values[slot as usize]
+    }
+
+    pub fn values(&self) -> &[T] {
+        &self.values
+    }
+
+    /// stores the value in a free slot, or a new one
+    pub fn insert(&mut self, value: T) -> u32 {
+        match self.free.pop() {
+            Some(slot) => {
+                self.set(slot, value);
+                slot
+            }
+            None => self.push(value),
+        }
+    }
+
+    pub fn set(&mut self, slot: u32, value: T) {
+        self.values[slot as usize] = value;
+        self.dirty.push(slot);
+    }
+
+    /// marks the slot as reusable, its content stays on the gpu
+    pub fn remove(&mut self, slot: u32) {
+        self.free.push(slot);
+    }
+
+    /// appends a contiguous range, ignoring free slots
+    pub fn extend(&mut self, values: impl IntoIterator<Item = T>) -> Range<u32> {
+        let start = self.values.len() as u32;
+        for value in values {
+            self.push(value);
+        }
+        start..self.values.len() as u32
+    }
+
+    /// replaces every slot
+    pub fn replace(&mut self, values: Vec<T>) {
+        self.values = values;
+        self.free.clear();
+        self.dirty.clear();
+        self.dirty.extend(0..self.values.len() as u32);
+    }
+
+    fn push(&mut self, value: T) -> u32 {
+        let slot = self.values.len() as u32;
+        self.values.push(value);
+        self.dirty.push(slot);
+        slot
+    }
+
+    pub fn binding(&self) -> Option<BindingResource<'_>> {
+        Some(self.buffer.as_ref()?.as_entire_binding())
+    }
+
+    pub fn write_buffer(&mut self, device: &RenderDevice, queue: &RenderQueue) {
+        let stride = T::SHADER_SIZE.get();
+        let capacity = self.buffer.as_ref().map(|b| b.size()).unwrap_or(0);
+
+        if capacity < self.values.len().max(1) as u64 * stride {
+            // grow in powers of two, the new buffer needs a full upload
+            let slots = self.values.len().max(1).next_power_of_two() as u64;
+            self.buffer = Some(device.create_buffer(&BufferDescriptor {
+                label: Some(self.label),
+                size: slots * stride,
+                usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }));
+            self.dirty.clear();
+            self.dirty.extend(0..self.values.len() as u32);
+        }
+
+        let Some(buffer) = self.buffer.as_ref() else {
+            return;
+        };
+
+        self.dirty.sort_unstable();
+        self.dirty.dedup();
+
+        let mut scratch = encase::StorageBuffer::new(Vec::<u8>::new());
+        let mut bytes = Vec::new();
+        let mut index = 0;
+        while index < self.dirty.len() {
+            let start = self.dirty[index];
+            let mut end = start + 1;
+            index += 1;
+            while index < self.dirty.len() && self.dirty[index] == end {
+                end += 1;
+                index += 1;
+            }
+
+            bytes.clear();
+            for value in self.values[start as usize..end as usize].iter() {
+                scratch.write(value).unwrap();
+                bytes.extend_from_slice(scratch.as_ref());
+            }
+            queue.write_buffer(buffer, start as u64 * stride, &bytes);
+        }
+
+        self.dirty.clear();
+    }
+}
@@ -0,0 +1,191 @@
+use crate::{
+    constant::{ABSORPTION_FORMAT, CASCADE_FORMAT, OCCLUDER_FORMAT, SDF_FORMAT, SHAPE_ID_FORMAT},
+    radiance::Probe,
+    sdf::{GpuCirlce, GpuEllipse, GpuMaterial, GpuPolygon, GpuRect, GpuSegment, GpuTileBuffer},
+    view::GiGpuConfig,
+};
+use bevy::{
+    prelude::*,
+    render::{
+        render_resource::{
+            binding_types::{
+                sampler, storage_buffer_read_only, texture_2d, texture_storage_2d, uniform_buffer,
+            },
+            BindGroupLayout, BindGroupLayoutEntries, CachedComputePipelineId,
+            ComputePipelineDescriptor, PipelineCache, SamplerBindingType, ShaderStages,
+            StorageTextureAccess, TextureSampleType,
+        },
+        renderer::RenderDevice,
+        view::ViewUniform,
+    },
+};
+
+/// threads per workgroup along each axis, the `@workgroup_size`
+/// of the sdf and cascade shaders
+pub(crate) const WORKGROUP_SIZE: u32 = 8;
+
+/// How the sdf and the cascades are built. Compute writes straight
+/// into storage textures, lifting the colour attachment limits.
+///
+/// The compute cascades run one thread per probe instead of one per texel,
+/// a probe spans `probe_base` texel per side. A workgroup loads the last
+/// cascade's probes it merges with into shared memory once, instead of
+/// every ray sampling them. Probes of the first cascade inside an emitter
+/// or a solid occluder are resolved from a single sample, their rays end
+/// where they start. The emission pass of each light channel is a fragment
+/// pass with both.
+///
+/// Devices without compute shaders or enough storage textures, like
+/// WebGL2, keep using fragment passes.
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RadianceBackend {
+    /// fullscreen fragment passes, runs everywhere
+    #[default]
+    Fragment,
+    /// compute dispatches, one per cascade
+    Compute,
+}
+
+impl RadianceBackend {
+    /// whether the device can run this backend
+    pub(crate) fn supported(&self, render_device: &RenderDevice) -> bool {
+        let limits = render_device.limits();
+        match self {
+            RadianceBackend::Fragment => true,
+            // the sdf pass writes four storage textures
+            RadianceBackend::Compute => {
+                limits.max_compute_workgroups_per_dimension > 0
+                    && limits.max_storage_textures_per_shader_stage >= 4
+            }
+        }
+    }
+}
+
+/// Compute versions of the sdf and cascade passes, only added with
+/// `RadianceBackend::Compute`. Until they are compiled the fragment
+/// passes are used.
+#[derive(Resource)]
+pub struct RadianceComputePipeline {
+    pub sdf_layout: BindGroupLayout,
+    pub sdf_id: CachedComputePipelineId,
+    pub cascade_layout: BindGroupLayout,
+    pub cascade_id: CachedComputePipelineId,
+}
+
+impl FromWorld for RadianceComputePipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        let sdf_layout = create_sdf_layout(render_device);
+        let cascade_layout = create_cascade_layout(render_device);
+        let server = world.resource::<AssetServer>();
+        let sdf_shader = server.load("embedded://solis_2d/shaders/sdf.wgsl");
+        let cascade_shader = server.load("embedded://solis_2d/shaders/cascade.wgsl");
+        let cache = world.resource::<PipelineCache>();
+
+        let sdf_id = cache.queue_compute_pipeline(ComputePipelineDescriptor {
+            label: Some("sdf_compute_pipeline".into()),
+            layout: vec![sdf_layout.clone()],
+            push_constant_ranges: vec![],
+            shader: sdf_shader,
+            shader_defs: vec![],
+            entry_point: "compute".into(),
+        });
+
+        let cascade_id = cache.queue_compute_pipeline(ComputePipelineDescriptor {
+            label: Some("cascade_compute_pipeline".into()),
+            layout: vec![cascade_layout.clone()],
+            push_constant_ranges: vec![],
+            shader: cascade_shader,
+            shader_defs: vec!["COMPUTE".into()],
+            entry_point: "compute".into(),
+        });
+
+        Self {
+            sdf_layout,
+            sdf_id,
+            cascade_layout,
+            cascade_id,
+        }
+    }
+}
+
+/// the sdf pass bindings, followed by its four targets
+fn create_sdf_layout(render_device: &RenderDevice) -> BindGroupLayout {
+    render_device.create_bind_group_layout(
+        "sdf_compute_layout",
+        &BindGroupLayoutEntries::sequential(
+            ShaderStages::COMPUTE,
+            (
+                uniform_buffer::<ViewUniform>(true),
+                storage_buffer_read_only::<Vec<GpuCirlce>>(false),
+                storage_buffer_read_only::<Vec<GpuRect>>(false),
+                storage_buffer_read_only::<Vec<GpuPolygon>>(false),
+                storage_buffer_read_only::<Vec<Vec2>>(false),
+                storage_buffer_read_only::<Vec<GpuSegment>>(false),
+                storage_buffer_read_only::<Vec<GpuEllipse>>(false),
+                uniform_buffer::<GiGpuConfig>(false),
+                // sprite occluder jump flood
+                texture_2d(TextureSampleType::Float { filterable: false }),
+                // sprite occluder mask
+                texture_2d(TextureSampleType::Float { filterable: true }),
+                storage_buffer_read_only::<GpuTileBuffer>(false),
+                storage_buffer_read_only::<Vec<GpuMaterial>>(false),
+                // textured light emission
+                texture_2d(TextureSampleType::Float { filterable: true }),
+                // sdf, occluder, absorption and shape id targets
+                texture_storage_2d(SDF_FORMAT, StorageTextureAccess::WriteOnly),
+                texture_storage_2d(OCCLUDER_FORMAT, StorageTextureAccess::WriteOnly),
+                texture_storage_2d(ABSORPTION_FORMAT, StorageTextureAccess::WriteOnly),
+                texture_storage_2d(SHAPE_ID_FORMAT, StorageTextureAccess::WriteOnly),
+            ),
+        ),
+    )
+}
+
+/// the cascade pass bindings, followed by the cascade target
+fn create_cascade_layout(render_device: &RenderDevice) -> BindGroupLayout {
+    render_device.create_bind_group_layout(
+        "cascade_compute_layout",
+        &BindGroupLayoutEntries::sequential(
+            ShaderStages::COMPUTE,
+            (
+                // sdf
+                texture_2d(TextureSampleType::Float { filterable: true }),
+                // occluder distance
+                texture_2d(TextureSampleType::Float { filterable: true }),
+                // occluder absorption
+                texture_2d(TextureSampleType::Float { filterable: true }),
+                // spotlight facing
+                texture_2d(TextureSampleType::Float { filterable: true }),
+                // last cascade
+                texture_2d(TextureSampleType::Float { filterable: true }),
+                // normal
+                texture_2d(TextureSampleType::Float { filterable: true }),
+                // environment
+                texture_2d(TextureSampleType::Float { filterable: true }),
+                // light cookie atlas
+                texture_2d(TextureSampleType::Float { filterable: true }),
+                // radiance sampler
+                sampler(SamplerBindingType::Filtering),
+                uniform_buffer::<GiGpuConfig>(false),
+                uniform_buffer::<Probe>(true),
+                // this cascade
+                texture_storage_2d(CASCADE_FORMAT, StorageTextureAccess::WriteOnly),
+            ),
+        ),
+    )
+}
+
+/// workgroups covering a target of `size` texel
+pub(crate) fn workgroups(size: UVec2) -> UVec2 {
+    (size + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE
+}
+
+/// workgroups of a cascade, one thread per probe. Each direction block gets
+/// its own workgroups, the last one per axis also takes the leftover probes.
+pub(crate) fn cascade_workgroups(cascade_size: UVec2, cascade_index: u32) -> UVec2 {
+    let sqr_angular = 1 << cascade_index;
+    let extent = cascade_size / sqr_angular;
+    let tiles = (cascade_size - (sqr_angular - 1) * extent + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE;
+    tiles * sqr_angular
+}
@@ -4,3 +4,22 @@ use bevy::{prelude::*, render::render_resource::TextureFormat};
 pub(crate) const COMMON_SHADER: Handle<Shader> = Handle::weak_from_u128(33512314255795372531210625);
 pub(crate) const SDF_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
 pub(crate) const CASCADE_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+/// distance and height of the closest occluder, negative for translucent ones,
+/// height and range of the closest light. lights without an occluder are see through.
+pub(crate) const OCCLUDER_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+/// absorption per scaled pixel of the closest occluder,
+/// alpha is the falloff shape of the closest light.
+pub(crate) const ABSORPTION_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+/// screen space facing angle, spotlight cone cosines and cookie row
+/// of the closest light.
+pub(crate) const SPOT_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+/// material of the closest light and binned item of the closest occluder,
+/// read back by the emission pass of each light channel.
+pub(crate) const SHAPE_ID_FORMAT: TextureFormat = TextureFormat::Rg32Uint;
+/// light cookie atlas, one row of angular masks per cookie
+pub(crate) const COOKIE_FORMAT: TextureFormat = TextureFormat::Rgba8Unorm;
+/// coverage of sprites per light channel they are not lit by, at native resolution
+pub(crate) const LIGHTING_MASK_FORMAT: TextureFormat = TextureFormat::Rgba8Unorm;
+pub(crate) const JFA_FORMAT: TextureFormat = TextureFormat::Rg32Float;
+/// screen tile size in native pixel used to bin sdf shapes
+pub(crate) const SDF_TILE_SIZE: u32 = 32;
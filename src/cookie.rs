@@ -0,0 +1,234 @@
+use crate::{buffer::SlotBuffer, constant::COOKIE_FORMAT};
+use bevy::{
+    ecs::entity::EntityHashMap,
+    prelude::*,
+    render::{
+        render_resource::{
+            binding_types::{sampler, storage_buffer_read_only, texture_2d},
+            AddressMode, BindGroupLayout, BindGroupLayoutEntries, CachedRenderPipelineId,
+            ColorTargetState, ColorWrites, Extent3d, FilterMode, FragmentState, MultisampleState,
+            PipelineCache, PrimitiveState, RenderPipelineDescriptor, Sampler, SamplerBindingType,
+            SamplerDescriptor, ShaderStages, ShaderType, TextureDescriptor, TextureDimension,
+            TextureSampleType, TextureUsages, TextureViewDescriptor, VertexState,
+        },
+        renderer::{RenderDevice, RenderQueue},
+        texture::CachedTexture,
+        Extract,
+    },
+};
+
+/// angular resolution of a cookie
+pub(crate) const COOKIE_RESOLUTION: u32 = 256;
+
+const COOKIE_TEXTURE: u32 = 0;
+const COOKIE_BEAMS: u32 = 1;
+
+/// Masks the light of a `Light` by direction, like window blinds or a
+/// lighthouse. Angles are measured counter clockwise from the local x axis,
+/// rotate the entity to turn the pattern.
+#[derive(Component, Clone)]
+pub enum LightCookie {
+    /// 1D mask around the light, the texture is read along its x axis
+    Texture(Handle<Image>),
+    /// `count` evenly spaced beams, `width` is the lit share of each
+    Beams { count: u32, width: f32 },
+}
+
+#[derive(ShaderType, Clone, Default)]
+pub struct GpuCookie {
+    kind: u32,
+    count: f32,
+    width: f32,
+}
+
+#[derive(Resource)]
+pub struct CookiePipeline {
+    pub layout: BindGroupLayout,
+    pub id: CachedRenderPipelineId,
+    pub sampler: Sampler,
+}
+
+impl FromWorld for CookiePipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        let layout = render_device.create_bind_group_layout(
+            "cookie_layout",
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::FRAGMENT,
+                (
+                    // cookie texture
+                    texture_2d(TextureSampleType::Float { filterable: true }),
+                    sampler(SamplerBindingType::Filtering),
+                    storage_buffer_read_only::<Vec<GpuCookie>>(false),
+                ),
+            ),
+        );
+
+        let sampler = render_device.create_sampler(&SamplerDescriptor {
+            label: Some("cookie sampler"),
+            address_mode_u: AddressMode::Repeat,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..default()
+        });
+
+        let server = world.resource::<AssetServer>();
+        let shader = server.load("embedded://solis_2d/shaders/cookie.wgsl");
+
+        let id =
+            world
+                .resource::<PipelineCache>()
+                .queue_render_pipeline(RenderPipelineDescriptor {
+                    label: Some("cookie_pipeline".into()),
+                    layout: vec![layout.clone()],
+                    push_constant_ranges: vec![],
+                    vertex: VertexState {
+                        shader: shader.clone(),
+                        shader_defs: vec![],
+                        entry_point: "vertex".into(),
+                        buffers: vec![],
+                    },
+                    primitive: PrimitiveState::default(),
+                    depth_stencil: None,
+                    multisample: MultisampleState::default(),
+                    fragment: Some(FragmentState {
+                        shader,
+                        shader_defs: vec![],
+                        entry_point: "fragment".into(),
+                        targets: vec![Some(ColorTargetState {
+                            format: COOKIE_FORMAT,
+                            blend: None,
+                            write_mask: ColorWrites::ALL,
+                        })],
+                    }),
+                });
+
+        Self {
+            layout,
+            id,
+            sampler,
+        }
+    }
+}
+
+/// One atlas row per cookie. Rows stay put while their light keeps
+/// the cookie, so materials only change with the light.
+#[derive(Resource)]
+pub struct LightCookies {
+    pub buffer: SlotBuffer<GpuCookie>,
+    /// texture of each row, `None` for procedural cookies
+    pub images: Vec<Option<AssetId<Image>>>,
+    pub rows: EntityHashMap<u32>,
+    pub atlas: Option<CachedTexture>,
+}
+
+impl Default for LightCookies {
+    fn default() -> Self {
+        Self {
+            buffer: SlotBuffer::new("light_cookie_buffer"),
+            images: Vec::new(),
+            rows: EntityHashMap::default(),
+            atlas: None,
+        }
+    }
+}
+
+impl LightCookies {
+    /// atlas row of an entity's cookie plus one, zero without a cookie
+    pub(crate) fn cookie(&self, entity: Entity) -> u32 {
+        self.rows.get(&entity).map_or(0, |row| row + 1)
+    }
+
+    fn upsert(&mut self, entity: Entity, cookie: &LightCookie) {
+        let (gpu_cookie, image) = match cookie {
+            LightCookie::Texture(image) => (
+                GpuCookie {
+                    kind: COOKIE_TEXTURE,
+                    ..default()
+                },
+                Some(image.id()),
+            ),
+            LightCookie::Beams { count, width } => (
+                GpuCookie {
+                    kind: COOKIE_BEAMS,
+                    count: *count as f32,
+                    width: width.clamp(0., 1.),
+                },
+                None,
+            ),
+        };
+
+        let row = match self.rows.get(&entity) {
+            Some(row) => {
+                self.buffer.set(*row, gpu_cookie);
+                *row
+            }
+            None => self.buffer.insert(gpu_cookie),
+        };
+
+        self.rows.insert(entity, row);
+        self.images.resize(self.buffer.len(), None);
+        self.images[row as usize] = image;
+    }
+
+    fn remove(&mut self, entity: Entity) {
+        if let Some(row) = self.rows.remove(&entity) {
+            self.buffer.remove(row);
+            self.images[row as usize] = None;
+        }
+    }
+}
+
+/// runs before the lights are extracted, which look up their cookie row
+pub(crate) fn extract_light_cookies(
+    cookies: Extract<Query<(Entity, &LightCookie), Changed<LightCookie>>>,
+    mut removed: Extract<RemovedComponents<LightCookie>>,
+    mut light_cookies: ResMut<LightCookies>,
+) {
+    for entity in removed.read() {
+        light_cookies.remove(entity);
+    }
+
+    for (entity, cookie) in cookies.iter() {
+        light_cookies.upsert(entity, cookie);
+    }
+}
+
+pub(crate) fn prepare_light_cookies(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    mut light_cookies: ResMut<LightCookies>,
+) {
+    light_cookies
+        .buffer
+        .write_buffer(&render_device, &render_queue);
+
+    let rows = light_cookies.buffer.len().max(1) as u32;
+    if light_cookies
+        .atlas
+        .as_ref()
+        .is_some_and(|atlas| atlas.texture.height() >= rows)
+    {
+        return;
+    }
+
+    let texture = render_device.create_texture(&TextureDescriptor {
+        label: Some("light_cookie_atlas"),
+        size: Extent3d {
+            width: COOKIE_RESOLUTION,
+            height: rows.next_power_of_two(),
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: COOKIE_FORMAT,
+        usage: TextureUsages::TEXTURE_BINDING | TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+    });
+
+    light_cookies.atlas = Some(CachedTexture {
+        default_view: texture.create_view(&TextureViewDescriptor::default()),
+        texture,
+    });
+}
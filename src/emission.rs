@@ -0,0 +1,57 @@
+use crate::{
+    sdf::{DisableEmitter, Light},
+    sprite::{ExtractedSpriteQuad, SpriteQuad, SpriteQuadBuffers},
+};
+use bevy::{
+    prelude::*,
+    render::{view::RenderLayers, Extract},
+};
+
+/// Colours a `Light` with a texture instead of the flat `Light::color`.
+/// The texture is placed like the entity's `Sprite`, tinted by the light
+/// colour and intensity, and only emits inside the light shape.
+#[derive(Component, Clone, Default)]
+pub struct LightTexture {
+    /// separate emission texture, the sprite texture if `None`
+    pub image: Option<Handle<Image>>,
+}
+
+/// textured lights drawn into the emission target
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct LightTextureBuffers(pub SpriteQuadBuffers);
+
+type LightTextureItem = (
+    &'static LightTexture,
+    &'static Light,
+    Option<&'static Sprite>,
+    Option<&'static Handle<Image>>,
+    &'static GlobalTransform,
+    &'static InheritedVisibility,
+    Option<&'static RenderLayers>,
+);
+
+pub fn extract_light_textures(
+    lights: Extract<Query<LightTextureItem, Without<DisableEmitter>>>,
+    mut buffers: ResMut<LightTextureBuffers>,
+) {
+    buffers.extracted.clear();
+
+    for (texture, light, sprite, sprite_image, global, ihview, layers) in lights.iter() {
+        if !ihview.get() {
+            continue;
+        }
+
+        let Some(image) = texture.image.as_ref().or(sprite_image) else {
+            continue;
+        };
+
+        buffers.extracted.push(ExtractedSpriteQuad {
+            image: image.id(),
+            quad: SpriteQuad::new(sprite.unwrap_or(&Sprite::default()), global),
+            alpha_threshold: 0.,
+            emit: light.color.to_linear().to_vec3() * light.intensity,
+            channels: 0,
+            layers: layers.cloned().unwrap_or_default(),
+        });
+    }
+}
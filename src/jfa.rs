@@ -0,0 +1,342 @@
+use crate::{
+    constant::{JFA_FORMAT, LIGHTING_MASK_FORMAT, SDF_FORMAT},
+    receiver::{light_channels, LightChannels},
+    sprite::{ExtractedSpriteQuad, GpuSpriteQuad, SpriteQuad, SpriteQuadBuffers},
+    view::GiGpuConfig,
+};
+use bevy::{
+    core_pipeline::fullscreen_vertex_shader::fullscreen_shader_vertex_state,
+    prelude::*,
+    render::{
+        render_resource::{
+            binding_types::{sampler, texture_2d, uniform_buffer},
+            BindGroupLayout, BindGroupLayoutEntries, BlendComponent, BlendFactor, BlendOperation,
+            BlendState, CachedRenderPipelineId, ColorTargetState, ColorWrites, FilterMode,
+            FragmentState, MultisampleState, PipelineCache, PrimitiveState,
+            RenderPipelineDescriptor, Sampler, SamplerBindingType, SamplerDescriptor, ShaderStages,
+            ShaderType, TextureSampleType, VertexState,
+        },
+        renderer::RenderDevice,
+        view::{RenderLayers, ViewUniform},
+        Extract,
+    },
+};
+
+/// Turns the alpha of a sprite into an occluder.
+/// The sprite is rendered into a mask each frame and a jump flood
+/// pass builds the distance field from it.
+#[derive(Component, Clone)]
+pub struct SpriteOccluder {
+    /// texels with an alpha above this value occlude
+    pub alpha_threshold: f32,
+    pub intensity: f32,
+    pub color: Color,
+}
+
+impl Default for SpriteOccluder {
+    fn default() -> Self {
+        Self {
+            alpha_threshold: 0.5,
+            intensity: 0.,
+            color: Color::BLACK,
+        }
+    }
+}
+
+#[derive(Resource)]
+pub struct JfaPipeline {
+    pub mask_view_layout: BindGroupLayout,
+    pub mask_sprite_layout: BindGroupLayout,
+    pub mask_id: CachedRenderPipelineId,
+    pub emission_id: CachedRenderPipelineId,
+    pub lighting_id: CachedRenderPipelineId,
+    pub layer_layout: BindGroupLayout,
+    pub layer_id: CachedRenderPipelineId,
+    pub layer_sampler: Sampler,
+    pub seed_layout: BindGroupLayout,
+    pub seed_id: CachedRenderPipelineId,
+    pub flood_layout: BindGroupLayout,
+    pub flood_id: CachedRenderPipelineId,
+}
+
+impl FromWorld for JfaPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+
+        let mask_view_layout = render_device.create_bind_group_layout(
+            "mask_view_layout",
+            &BindGroupLayoutEntries::single(
+                ShaderStages::VERTEX,
+                uniform_buffer::<ViewUniform>(true),
+            ),
+        );
+
+        let mask_sprite_layout = render_device.create_bind_group_layout(
+            "mask_sprite_layout",
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::VERTEX_FRAGMENT,
+                (
+                    // sprite texture
+                    texture_2d(TextureSampleType::Float { filterable: true }),
+                    // sprite sampler
+                    sampler(SamplerBindingType::Filtering),
+                    uniform_buffer::<GpuSpriteQuad>(true),
+                ),
+            ),
+        );
+
+        let layer_layout = render_device.create_bind_group_layout(
+            "mask_layer_layout",
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::FRAGMENT,
+                (
+                    // occlusion layer
+                    texture_2d(TextureSampleType::Float { filterable: true }),
+                    // emission layer
+                    texture_2d(TextureSampleType::Float { filterable: true }),
+                    sampler(SamplerBindingType::Filtering),
+                    uniform_buffer::<GiGpuConfig>(false),
+                ),
+            ),
+        );
+
+        let layer_sampler = render_device.create_sampler(&SamplerDescriptor {
+            label: Some("layer sampler"),
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..default()
+        });
+
+        let seed_layout = render_device.create_bind_group_layout(
+            "seed_layout",
+            &BindGroupLayoutEntries::single(
+                ShaderStages::FRAGMENT,
+                // mask
+                texture_2d(TextureSampleType::Float { filterable: true }),
+            ),
+        );
+
+        let flood_layout = render_device.create_bind_group_layout(
+            "flood_layout",
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::FRAGMENT,
+                (
+                    // last flood
+                    texture_2d(TextureSampleType::Float { filterable: false }),
+                    uniform_buffer::<JfaStep>(true),
+                ),
+            ),
+        );
+
+        let server = world.resource::<AssetServer>();
+        let mask_shader = server.load("embedded://solis_2d/shaders/mask.wgsl");
+        let jfa_shader = server.load("embedded://solis_2d/shaders/jfa.wgsl");
+        let cache = world.resource::<PipelineCache>();
+
+        let mask_id = cache.queue_render_pipeline(RenderPipelineDescriptor {
+            label: Some("mask_pipeline".into()),
+            layout: vec![mask_view_layout.clone(), mask_sprite_layout.clone()],
+            push_constant_ranges: vec![],
+            vertex: VertexState {
+                shader: mask_shader.clone(),
+                shader_defs: vec![],
+                entry_point: "vertex".into(),
+                buffers: vec![],
+            },
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState {
+                shader: mask_shader.clone(),
+                shader_defs: vec![],
+                entry_point: "fragment".into(),
+                targets: vec![Some(ColorTargetState {
+                    format: SDF_FORMAT,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+        });
+
+        let emission_id = cache.queue_render_pipeline(RenderPipelineDescriptor {
+            label: Some("emission_pipeline".into()),
+            layout: vec![mask_view_layout.clone(), mask_sprite_layout.clone()],
+            push_constant_ranges: vec![],
+            vertex: VertexState {
+                shader: mask_shader.clone(),
+                shader_defs: vec![],
+                entry_point: "vertex".into(),
+                buffers: vec![],
+            },
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState {
+                shader: mask_shader.clone(),
+                shader_defs: vec![],
+                entry_point: "emission".into(),
+                targets: vec![Some(ColorTargetState {
+                    format: SDF_FORMAT,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+        });
+
+        // overlapping sprites keep the highest coverage per channel
+        let max_blend = BlendComponent {
+            src_factor: BlendFactor::One,
+            dst_factor: BlendFactor::One,
+            operation: BlendOperation::Max,
+        };
+        let lighting_id = cache.queue_render_pipeline(RenderPipelineDescriptor {
+            label: Some("lighting_mask_pipeline".into()),
+            layout: vec![mask_view_layout.clone(), mask_sprite_layout.clone()],
+            push_constant_ranges: vec![],
+            vertex: VertexState {
+                shader: mask_shader.clone(),
+                shader_defs: vec![],
+                entry_point: "vertex".into(),
+                buffers: vec![],
+            },
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState {
+                shader: mask_shader.clone(),
+                shader_defs: vec![],
+                entry_point: "lighting".into(),
+                targets: vec![Some(ColorTargetState {
+                    format: LIGHTING_MASK_FORMAT,
+                    blend: Some(BlendState {
+                        color: max_blend,
+                        alpha: max_blend,
+                    }),
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+        });
+
+        let layer_id = cache.queue_render_pipeline(RenderPipelineDescriptor {
+            label: Some("mask_layer_pipeline".into()),
+            layout: vec![layer_layout.clone()],
+            push_constant_ranges: vec![],
+            vertex: fullscreen_shader_vertex_state(),
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState {
+                shader: mask_shader,
+                shader_defs: vec![],
+                entry_point: "layers".into(),
+                targets: vec![Some(ColorTargetState {
+                    format: SDF_FORMAT,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+        });
+
+        let seed_id = cache.queue_render_pipeline(RenderPipelineDescriptor {
+            label: Some("seed_pipeline".into()),
+            layout: vec![seed_layout.clone()],
+            push_constant_ranges: vec![],
+            vertex: fullscreen_shader_vertex_state(),
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState {
+                shader: jfa_shader.clone(),
+                shader_defs: vec![],
+                entry_point: "seed".into(),
+                targets: vec![Some(ColorTargetState {
+                    format: JFA_FORMAT,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+        });
+
+        let flood_id = cache.queue_render_pipeline(RenderPipelineDescriptor {
+            label: Some("flood_pipeline".into()),
+            layout: vec![flood_layout.clone()],
+            push_constant_ranges: vec![],
+            vertex: fullscreen_shader_vertex_state(),
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState {
+                shader: jfa_shader,
+                shader_defs: vec![],
+                entry_point: "flood".into(),
+                targets: vec![Some(ColorTargetState {
+                    format: JFA_FORMAT,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+        });
+
+        Self {
+            mask_view_layout,
+            mask_sprite_layout,
+            mask_id,
+            emission_id,
+            lighting_id,
+            layer_layout,
+            layer_id,
+            layer_sampler,
+            seed_layout,
+            seed_id,
+            flood_layout,
+            flood_id,
+        }
+    }
+}
+
+#[derive(ShaderType, Debug, Clone, Copy)]
+pub struct JfaStep {
+    /// sample distance in pixel
+    pub step: u32,
+}
+
+// ---------------------------
+// extract
+
+/// sprite occluders drawn into the occluder mask
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct SpriteOccluderBuffers(pub SpriteQuadBuffers);
+
+type SpriteOccluderItem = (
+    &'static SpriteOccluder,
+    &'static Sprite,
+    &'static Handle<Image>,
+    &'static GlobalTransform,
+    &'static InheritedVisibility,
+    Option<&'static RenderLayers>,
+    Option<&'static LightChannels>,
+);
+
+pub fn extract_sprite_occluders(
+    occluders: Extract<Query<SpriteOccluderItem>>,
+    mut buffers: ResMut<SpriteOccluderBuffers>,
+) {
+    buffers.extracted.clear();
+
+    // visibility per view is left to the mask pass, which only
+    // draws sprites sharing a layer with the view.
+    for (occluder, sprite, image, global, ihview, layers, channels) in occluders.iter() {
+        if !ihview.get() {
+            continue;
+        }
+
+        buffers.extracted.push(ExtractedSpriteQuad {
+            image: image.id(),
+            quad: SpriteQuad::new(sprite, global),
+            alpha_threshold: occluder.alpha_threshold,
+            emit: occluder.color.to_linear().to_vec3() * occluder.intensity,
+            channels: light_channels(channels),
+            layers: layers.cloned().unwrap_or_default(),
+        });
+    }
+}
@@ -0,0 +1,171 @@
+use bevy::{
+    core_pipeline::tonemapping::{DebandDither, Tonemapping},
+    prelude::*,
+    render::{
+        camera::RenderTarget,
+        extract_component::ExtractComponent,
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat, TextureUsages},
+        view::RenderLayers,
+    },
+};
+
+/// Lets whole render layers take part in the light simulation.
+/// Add this to a camera with a `RadianceConfig`.
+///
+/// Everything drawn on the `occlusion` layers blocks light, everything
+/// drawn on the `emission` layers emits its color and occludes as well.
+/// The cameras and render targets are managed by solis.
+#[derive(Component, Clone)]
+pub struct LayerMasks {
+    pub occlusion: RenderLayers,
+    pub emission: RenderLayers,
+    /// pixels with an alpha above this value are part of the mask
+    pub alpha_threshold: f32,
+}
+
+impl Default for LayerMasks {
+    fn default() -> Self {
+        Self {
+            occlusion: RenderLayers::none(),
+            emission: RenderLayers::none(),
+            alpha_threshold: 0.5,
+        }
+    }
+}
+
+/// the cameras spawned for `LayerMasks`
+#[derive(Component, ExtractComponent, Clone)]
+pub struct LayerCameras {
+    pub occlusion_camera: Entity,
+    pub emission_camera: Entity,
+    pub occlusion: Handle<Image>,
+    pub emission: Handle<Image>,
+    pub alpha_threshold: f32,
+}
+
+pub(crate) fn sync_layer_cameras(
+    mut cmd: Commands,
+    mut images: ResMut<Assets<Image>>,
+    mut views: Query<(
+        Entity,
+        &Camera,
+        &OrthographicProjection,
+        &LayerMasks,
+        Option<&mut LayerCameras>,
+    )>,
+    mut layer_cameras: Query<(&mut OrthographicProjection, &mut RenderLayers), Without<LayerMasks>>,
+    orphans: Query<(Entity, &LayerCameras), Without<LayerMasks>>,
+) {
+    for (entity, cameras) in orphans.iter() {
+        cmd.entity(cameras.occlusion_camera).despawn_recursive();
+        cmd.entity(cameras.emission_camera).despawn_recursive();
+        cmd.entity(entity).remove::<LayerCameras>();
+    }
+
+    for (entity, camera, projection, masks, cameras) in views.iter_mut() {
+        let Some(size) = camera.physical_target_size() else {
+            continue;
+        };
+
+        let Some(mut cameras) = cameras else {
+            let occlusion = images.add(create_layer_image(size));
+            let emission = images.add(create_layer_image(size));
+            let mut spawn_camera = |image: &Handle<Image>, layers: &RenderLayers| {
+                cmd.spawn((
+                    Camera2dBundle {
+                        camera: Camera {
+                            order: camera.order - 1,
+                            target: RenderTarget::Image(image.clone()),
+                            clear_color: ClearColorConfig::Custom(Color::NONE),
+                            hdr: true,
+                            ..default()
+                        },
+                        projection: projection.clone(),
+                        transform: Transform::IDENTITY,
+                        tonemapping: Tonemapping::None,
+                        deband_dither: DebandDither::Disabled,
+                        ..default()
+                    },
+                    layers.clone(),
+                ))
+                .set_parent(entity)
+                .id()
+            };
+
+            let occlusion_camera = spawn_camera(&occlusion, &masks.occlusion);
+            let emission_camera = spawn_camera(&emission, &masks.emission);
+            cmd.entity(entity).insert(LayerCameras {
+                occlusion_camera,
+                emission_camera,
+                occlusion,
+                emission,
+                alpha_threshold: masks.alpha_threshold,
+            });
+            continue;
+        };
+
+        if cameras.alpha_threshold != masks.alpha_threshold {
+            cameras.alpha_threshold = masks.alpha_threshold;
+        }
+
+        // follow the radiance camera
+        for (camera_entity, layers) in [
+            (cameras.occlusion_camera, &masks.occlusion),
+            (cameras.emission_camera, &masks.emission),
+        ] {
+            let Ok((mut layer_projection, mut layer_layers)) = layer_cameras.get_mut(camera_entity)
+            else {
+                continue;
+            };
+            // writing unconditionally would flag them changed every frame
+            if !same_projection(&layer_projection, projection) {
+                *layer_projection = projection.clone();
+            }
+            layer_layers.set_if_neq(layers.clone());
+        }
+
+        for handle in [&cameras.occlusion, &cameras.emission] {
+            let Some(image) = images.get(handle) else {
+                continue;
+            };
+
+            if image.size() != size {
+                if let Some(image) = images.get_mut(handle) {
+                    image.resize(Extent3d {
+                        width: size.x,
+                        height: size.y,
+                        depth_or_array_layers: 1,
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// `OrthographicProjection` has no `PartialEq`, a new
+/// scaling mode shows up in the area it computes
+fn same_projection(a: &OrthographicProjection, b: &OrthographicProjection) -> bool {
+    a.near == b.near
+        && a.far == b.far
+        && a.viewport_origin == b.viewport_origin
+        && a.scale == b.scale
+        && a.area == b.area
+}
+
+fn create_layer_image(size: UVec2) -> Image {
+    let mut image = Image::new_fill(
+        Extent3d {
+            width: size.x,
+            height: size.y,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0; 8],
+        TextureFormat::Rgba16Float,
+        RenderAssetUsages::default(),
+    );
+    image.texture_descriptor.usage =
+        TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST | TextureUsages::RENDER_ATTACHMENT;
+    image
+}
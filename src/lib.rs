@@ -7,31 +7,57 @@ use bevy::{
     core_pipeline::core_2d::graph::{Core2d, Node2d},
     prelude::*,
     render::{
+        camera::CameraUpdateSystem,
         extract_component::ExtractComponentPlugin,
         render_graph::{RenderGraphApp, ViewNodeRunner},
         render_resource::Source,
-        view::{check_visibility, VisibilitySystems},
+        renderer::RenderDevice,
         Render, RenderApp, RenderSet,
     },
     time::common_conditions::on_timer,
 };
-use sdf::Emitter;
 use std::{path::PathBuf, time::Duration};
 
+mod animation;
+mod buffer;
+mod compute;
 mod constant;
+mod cookie;
+mod emission;
+mod jfa;
+mod layers;
 mod node;
 mod radiance;
+mod receiver;
 mod sdf;
+mod sprite;
+mod sun;
 mod view;
 
 pub mod prelude {
-    pub use super::sdf::{DisableEmitter, Emitter, SdfShape};
-    pub use super::view::{DisableLight, GiFlags, NormalTarget, RadianceConfig};
+    pub use super::animation::{AnimationMode, LightAnimation, LightKeyframe};
+    pub use super::compute::RadianceBackend;
+    pub use super::cookie::LightCookie;
+    pub use super::emission::LightTexture;
+    pub use super::jfa::SpriteOccluder;
+    pub use super::layers::LayerMasks;
+    pub use super::receiver::{LightChannels, ReceivesLight};
+    pub use super::sdf::{
+        Albedo, DisableEmitter, Emitter, Falloff, Light, NoShadow, Occluder, SdfShape, Spotlight,
+    };
+    pub use super::sun::DirectionalLight2d;
+    pub use super::view::{
+        DisableLight, Environment, GiFlags, NormalTarget, RadianceConfig, RadianceQuality,
+        TemporalFilter,
+    };
     pub use super::SolisPlugin;
 }
 
 #[derive(Default)]
-pub struct SolisPlugin;
+pub struct SolisPlugin {
+    /// fragment or compute passes for the sdf and the cascades
+    pub backend: compute::RadianceBackend,
+}
 
 impl Plugin for SolisPlugin {
     fn build(&self, app: &mut App) {
@@ -39,6 +65,7 @@ impl Plugin for SolisPlugin {
             ExtractComponentPlugin::<view::RadianceConfig>::default(),
             ExtractComponentPlugin::<view::NormalTarget>::default(),
             ExtractComponentPlugin::<view::DisableLight>::default(),
+            ExtractComponentPlugin::<layers::LayerCameras>::default(),
         ));
 
         // adds some hot reloading for dev
@@ -47,8 +74,12 @@ impl Plugin for SolisPlugin {
 
         app.add_systems(
             PostUpdate,
-            check_visibility::<With<Emitter>>.in_set(VisibilitySystems::CheckVisibility),
+            (
+                layers::sync_layer_cameras.before(CameraUpdateSystem),
+                (sdf::sync_emitters, animation::animate_lights).chain(),
+            ),
         );
+
         // ---------------
         // fix later
         // bevy's wgsl definition do not work with embedded assets
@@ -63,6 +94,10 @@ impl Plugin for SolisPlugin {
         embedded_asset!(app, "shaders/composite.wgsl");
         embedded_asset!(app, "shaders/cascade.wgsl");
         embedded_asset!(app, "shaders/mipmap.wgsl");
+        embedded_asset!(app, "shaders/temporal.wgsl");
+        embedded_asset!(app, "shaders/mask.wgsl");
+        embedded_asset!(app, "shaders/jfa.wgsl");
+        embedded_asset!(app, "shaders/cookie.wgsl");
         // ---------------
 
         let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
@@ -70,13 +105,27 @@ impl Plugin for SolisPlugin {
         };
 
         render_app
-            .add_systems(ExtractSchedule, sdf::extract_emitter)
+            .add_systems(
+                ExtractSchedule,
+                (
+                    (cookie::extract_light_cookies, sdf::extract_emitter).chain(),
+                    jfa::extract_sprite_occluders,
+                    emission::extract_light_textures,
+                    receiver::extract_lit_sprites,
+                    receiver::extract_light_channels,
+                    sun::extract_directional_lights,
+                ),
+            )
             .add_systems(
                 Render,
                 (
                     sdf::prepare_sdf_buffers,
-                    view::prepare_config,
-                    view::prepare_textures,
+                    sdf::prepare_sdf_tiles,
+                    sprite::prepare_sprite_quads::<jfa::SpriteOccluderBuffers>,
+                    sprite::prepare_sprite_quads::<emission::LightTextureBuffers>,
+                    sprite::prepare_sprite_quads::<receiver::LitSpriteBuffers>,
+                    cookie::prepare_light_cookies,
+                    (view::prepare_textures, view::prepare_config).chain(),
                 )
                     .in_set(RenderSet::Prepare),
             )
@@ -92,7 +141,28 @@ impl Plugin for SolisPlugin {
         render_app
             .init_resource::<sdf::SdfPipeline>()
             .init_resource::<sdf::SdfBuffers>()
+            .init_resource::<sdf::SdfTiles>()
+            .init_resource::<view::RadianceHistories>()
+            .init_resource::<sun::ExtractedDirectionalLights>()
+            .init_resource::<jfa::JfaPipeline>()
+            .init_resource::<jfa::SpriteOccluderBuffers>()
+            .init_resource::<emission::LightTextureBuffers>()
+            .init_resource::<receiver::LitSpriteBuffers>()
+            .init_resource::<receiver::ActiveLightChannels>()
+            .init_resource::<cookie::LightCookies>()
+            .init_resource::<cookie::CookiePipeline>()
             .init_resource::<radiance::RadiancePipeline>();
+
+        if self.backend == compute::RadianceBackend::Compute {
+            if self
+                .backend
+                .supported(render_app.world().resource::<RenderDevice>())
+            {
+                render_app.init_resource::<compute::RadianceComputePipeline>();
+            } else {
+                warn!("compute shaders are not supported, falling back to fragment passes");
+            }
+        }
     }
 }
 
@@ -1,7 +1,16 @@
 use crate::{
+    compute::{cascade_workgroups, workgroups, RadianceComputePipeline},
+    cookie::{CookiePipeline, LightCookies},
+    emission::LightTextureBuffers,
+    jfa::{JfaPipeline, SpriteOccluderBuffers},
+    layers::LayerCameras,
     radiance::RadiancePipeline,
-    sdf::{SdfBuffers, SdfPipeline},
-    view::{DisableLight, NormalTarget, RadianceBuffers, RadianceConfig, RadianceTargets},
+    receiver::LitSpriteBuffers,
+    sdf::{SdfBuffers, SdfPipeline, SdfTiles},
+    view::{
+        DisableLight, Environment, NormalTarget, RadianceBuffers, RadianceConfig, RadianceHistory,
+        RadianceTargets,
+    },
 };
 use bevy::{
     ecs::{query::QueryItem, system::lifetimeless::Read},
@@ -10,12 +19,12 @@ use bevy::{
         render_asset::RenderAssets,
         render_graph::{self, NodeRunError, RenderGraphContext, RenderLabel},
         render_resource::{
-            BindGroupEntries, Operations, PipelineCache, RenderPassColorAttachment,
-            RenderPassDescriptor,
+            BindGroupEntries, ComputePassDescriptor, ImageCopyTexture, Operations, Origin3d,
+            PipelineCache, RenderPassColorAttachment, RenderPassDescriptor, TextureAspect,
         },
         renderer::RenderContext,
-        texture::GpuImage,
-        view::{ViewTarget, ViewUniformOffset, ViewUniforms},
+        texture::{FallbackImage, GpuImage},
+        view::{RenderLayers, ViewTarget, ViewUniformOffset, ViewUniforms},
     },
 };
 
@@ -30,23 +39,32 @@ impl render_graph::ViewNode for LightNode {
         Read<ViewTarget>,
         Read<RadianceBuffers>,
         Read<RadianceTargets>,
+        Read<RadianceHistory>,
         Read<RadianceConfig>,
         Has<DisableLight>,
         Option<Read<NormalTarget>>,
+        Option<Read<LayerCameras>>,
+        Option<Read<RenderLayers>>,
     );
 
     fn run<'w>(
         &self,
-        _graph: &mut RenderGraphContext,
+        graph: &mut RenderGraphContext,
         render_context: &mut RenderContext<'w>,
-        (view_offset, view_target, radiance_buffers, radiance_targets, config, disabled, normal): QueryItem<
-            'w,
-            Self::ViewQuery,
-        >,
+        (
+            view_offset,
+            view_target,
+            radiance_buffers,
+            radiance_targets,
+            radiance_history,
+            config,
+            disabled,
+            normal,
+            layers,
+            view_layers,
+        ): QueryItem<'w, Self::ViewQuery>,
         world: &'w World,
     ) -> Result<(), NodeRunError> {
-
-
         if disabled {
             return Ok(());
         }
@@ -54,7 +72,17 @@ impl render_graph::ViewNode for LightNode {
         let sdf_pipeline = world.resource::<SdfPipeline>();
         let pipeline_cache = world.resource::<PipelineCache>();
         let sdf_buffers = world.resource::<SdfBuffers>();
+        let Some(sdf_tiles) = world.resource::<SdfTiles>().0.get(&graph.view_entity()) else {
+            return Ok(());
+        };
         let radiance_pipline = world.resource::<RadiancePipeline>();
+        let jfa_pipeline = world.resource::<JfaPipeline>();
+        let sprite_occluders = world.resource::<SpriteOccluderBuffers>();
+        let light_textures = world.resource::<LightTextureBuffers>();
+        let lit_sprites = world.resource::<LitSpriteBuffers>();
+        let light_cookies = world.resource::<LightCookies>();
+        let cookie_pipeline = world.resource::<CookiePipeline>();
+        let fallback_image = world.resource::<FallbackImage>();
         let post_process = view_target.post_process_write();
         let gpu_imges = world.resource::<RenderAssets<GpuImage>>();
 
@@ -63,10 +91,19 @@ impl render_graph::ViewNode for LightNode {
             .flatten()
             .unwrap_or(&radiance_targets.fallback.default_view);
 
+        let environment_view = match &config.environment {
+            Environment::Texture(image) => gpu_imges.get(image).map(|t| &t.texture_view),
+            _ => None,
+        }
+        .unwrap_or(&radiance_targets.fallback.default_view);
+
         // ------------------------------------
         // load piplines
 
-        let Some(sdf_render_pipeline) = pipeline_cache.get_render_pipeline(sdf_pipeline.id) else {
+        let (Some(sdf_render_pipeline), Some(sdf_emission_render_pipeline)) = (
+            pipeline_cache.get_render_pipeline(sdf_pipeline.id),
+            pipeline_cache.get_render_pipeline(sdf_pipeline.emission_id),
+        ) else {
             // warn!("sdf pipeline missing");
             return Ok(());
         };
@@ -85,6 +122,14 @@ impl render_graph::ViewNode for LightNode {
             return Ok(());
         };
 
+        let (Some(cookie_render_pipeline), Some(cookie_atlas)) = (
+            pipeline_cache.get_render_pipeline(cookie_pipeline.id),
+            light_cookies.atlas.as_ref(),
+        ) else {
+            // warn!("cookie pipeline missing")
+            return Ok(());
+        };
+
         let Some(mipmap_render_pipeline) =
             pipeline_cache.get_render_pipeline(radiance_pipline.mipmap_id)
         else {
@@ -92,6 +137,42 @@ impl render_graph::ViewNode for LightNode {
             return Ok(());
         };
 
+        // falls back to this frame's light until it is ready
+        let temporal_render_pipeline = config
+            .temporal
+            .and_then(|_| pipeline_cache.get_render_pipeline(radiance_pipline.temporal_id));
+
+        // compute passes once they are compiled, fragment passes until then
+        let compute = world
+            .get_resource::<RadianceComputePipeline>()
+            .and_then(|compute| {
+                Some((
+                    compute,
+                    pipeline_cache.get_compute_pipeline(compute.sdf_id)?,
+                    pipeline_cache.get_compute_pipeline(compute.cascade_id)?,
+                ))
+            });
+
+        let (
+            Some(mask_render_pipeline),
+            Some(emission_render_pipeline),
+            Some(lighting_render_pipeline),
+            Some(layer_render_pipeline),
+            Some(seed_render_pipeline),
+            Some(flood_render_pipeline),
+        ) = (
+            pipeline_cache.get_render_pipeline(jfa_pipeline.mask_id),
+            pipeline_cache.get_render_pipeline(jfa_pipeline.emission_id),
+            pipeline_cache.get_render_pipeline(jfa_pipeline.lighting_id),
+            pipeline_cache.get_render_pipeline(jfa_pipeline.layer_id),
+            pipeline_cache.get_render_pipeline(jfa_pipeline.seed_id),
+            pipeline_cache.get_render_pipeline(jfa_pipeline.flood_id),
+        )
+        else {
+            // warn!("jfa pipeline missing")
+            return Ok(());
+        };
+
         // ------------------------------------
 
         let Some(gi_config_binding) = radiance_buffers.config_buffer.binding() else {
@@ -104,33 +185,76 @@ impl render_graph::ViewNode for LightNode {
             return Ok(());
         };
 
-        let (Some(view_uniform_binding), Some(sdf_circle_binding), Some(sdf_rect_binding)) = (
+        let (
+            Some(view_uniform_binding),
+            Some(sdf_circle_binding),
+            Some(sdf_rect_binding),
+            Some(sdf_polygon_binding),
+            Some(sdf_vertex_binding),
+            Some(sdf_segment_binding),
+            Some(sdf_ellipse_binding),
+            Some(sdf_tile_binding),
+            Some(sdf_material_binding),
+        ) = (
             world.resource::<ViewUniforms>().uniforms.binding(),
             sdf_buffers.circle_buffer.binding(),
             sdf_buffers.rect_buffer.binding(),
-        ) else {
+            sdf_buffers.polygon_buffer.binding(),
+            sdf_buffers.vertex_buffer.binding(),
+            sdf_buffers.segment_buffer.binding(),
+            sdf_buffers.ellipse_buffer.binding(),
+            sdf_tiles.binding(),
+            sdf_buffers.material_buffer.binding(),
+        )
+        else {
             warn!("binding missing");
             return Ok(());
         };
 
         // ---------------------------------------------------------------
-        // create sdf texture
+        // sprite occluder mask
 
-        let sdf_bind_group = render_context.render_device().create_bind_group(
-            Some("sdf_bind_group".into()),
-            &sdf_pipeline.layout,
-            &BindGroupEntries::sequential((
-                view_uniform_binding.clone(),
-                sdf_circle_binding,
-                sdf_rect_binding,
-                gi_config_binding.clone(),
-            )),
+        let mask_view_bind_group = render_context.render_device().create_bind_group(
+            Some("mask_view_bind_group"),
+            &jfa_pipeline.mask_view_layout,
+            &BindGroupEntries::single(view_uniform_binding.clone()),
         );
+
+        // sprites are drawn by the views sharing one of their layers
+        let view_layers = view_layers.cloned().unwrap_or_default();
+        let sprite_bind_groups = sprite_occluders.bind_groups(
+            "mask_sprite_bind_group",
+            render_context.render_device(),
+            &jfa_pipeline.mask_sprite_layout,
+            gpu_imges,
+            &view_layers,
+        );
+
+        let layer_bind_group = layers
+            .and_then(|layers| {
+                Some((
+                    gpu_imges.get(&layers.occlusion)?,
+                    gpu_imges.get(&layers.emission)?,
+                ))
+            })
+            .map(|(occlusion, emission)| {
+                render_context.render_device().create_bind_group(
+                    Some("mask_layer_bind_group"),
+                    &jfa_pipeline.layer_layout,
+                    &BindGroupEntries::sequential((
+                        &occlusion.texture_view,
+                        &emission.texture_view,
+                        &jfa_pipeline.layer_sampler,
+                        gi_config_binding.clone(),
+                    )),
+                )
+            });
+
         {
             let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
-                label: Some("sdf_pass".into()),
+                label: Some("mask_pass"),
                 color_attachments: &[Some(RenderPassColorAttachment {
-                    view: &radiance_targets.sdf.default_view,
+                    view: &radiance_targets.mask.default_view,
                     resolve_target: None,
                     ops: Operations::default(),
                 })],
@@ -139,43 +263,36 @@ impl render_graph::ViewNode for LightNode {
                 occlusion_query_set: None,
             });
 
-            render_pass.set_render_pipeline(sdf_render_pipeline);
-            render_pass.set_bind_group(0, &sdf_bind_group, &[view_offset.offset]);
-            render_pass.draw(0..3, 0..1);
+            render_pass.set_render_pipeline(mask_render_pipeline);
+            render_pass.set_bind_group(0, &mask_view_bind_group, &[view_offset.offset]);
+            for (bind_group, offset) in sprite_bind_groups.iter() {
+                render_pass.set_bind_group(1, bind_group, &[*offset]);
+                render_pass.draw(0..6, 0..1);
+            }
+
+            if let Some(layer_bind_group) = layer_bind_group.as_ref() {
+                render_pass.set_render_pipeline(layer_render_pipeline);
+                render_pass.set_bind_group(0, layer_bind_group, &[]);
+                render_pass.draw(0..3, 0..1);
+            }
         }
 
         // ---------------------------------------------------------------
-        // ping pong cascades
-        for i in 0..(config.cascade_count as usize) {
-            let (current_target, last_target) = if i % 2 == 0 {
-                (
-                    &radiance_targets.merge1.default_view,
-                    &radiance_targets.merge0.default_view,
-                )
-            } else {
-                (
-                    &radiance_targets.merge0.default_view,
-                    &radiance_targets.merge1.default_view,
-                )
-            };
+        // textured light emission
 
-            let cascade_bind_group = render_context.render_device().create_bind_group(
-                Some("cascade_bind_group".into()),
-                &radiance_pipline.cascade_layout,
-                &BindGroupEntries::sequential((
-                    &radiance_targets.sdf.default_view,
-                    last_target,
-                    normal_view,
-                    &radiance_pipline.radiance_sampler,
-                    gi_config_binding.clone(),
-                    probe_binding.clone(),
-                )),
-            );
+        let texture_bind_groups = light_textures.bind_groups(
+            "emission_sprite_bind_group",
+            render_context.render_device(),
+            &jfa_pipeline.mask_sprite_layout,
+            gpu_imges,
+            &view_layers,
+        );
 
+        {
             let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
-                label: Some("cascade_pass".into()),
+                label: Some("emission_pass"),
                 color_attachments: &[Some(RenderPassColorAttachment {
-                    view: current_target,
+                    view: &radiance_targets.emission.default_view,
                     resolve_target: None,
                     ops: Operations::default(),
                 })],
@@ -184,31 +301,76 @@ impl render_graph::ViewNode for LightNode {
                 occlusion_query_set: None,
             });
 
-            let offset = radiance_buffers.probe_offsets[i];
-            render_pass.set_render_pipeline(cascade_render_pipeline);
-            render_pass.set_bind_group(0, &cascade_bind_group, &[offset]);
-            render_pass.draw(0..3, 0..1);
+            render_pass.set_render_pipeline(emission_render_pipeline);
+            render_pass.set_bind_group(0, &mask_view_bind_group, &[view_offset.offset]);
+            for (bind_group, offset) in texture_bind_groups.iter() {
+                render_pass.set_bind_group(1, bind_group, &[*offset]);
+                render_pass.draw(0..6, 0..1);
+            }
         }
 
         // ---------------------------------------------------------------
-        // mipmap
-        let mipmap_bind_group = render_context.render_device().create_bind_group(
-            Some("mipmap_bind_group".into()),
-            &radiance_pipline.mipmap_layout,
-            &BindGroupEntries::sequential((
-                if config.cascade_count % 2 == 0 {
-                    &radiance_targets.merge0.default_view
-                } else {
-                    &radiance_targets.merge1.default_view
-                },
-                gi_config_binding.clone(),
-            )),
+        // lighting mask, the light channels each sprite is not lit by
+
+        let lit_bind_groups = lit_sprites.bind_groups(
+            "lit_sprite_bind_group",
+            render_context.render_device(),
+            &jfa_pipeline.mask_sprite_layout,
+            gpu_imges,
+            &view_layers,
         );
+
         {
             let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
-                label: Some("mipmap_pass".into()),
+                label: Some("lighting_mask_pass"),
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view: &radiance_targets.lighting_mask.default_view,
+                    resolve_target: None,
+                    ops: Operations::default(),
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+
+            render_pass.set_render_pipeline(lighting_render_pipeline);
+            render_pass.set_bind_group(0, &mask_view_bind_group, &[view_offset.offset]);
+            for (bind_group, offset) in lit_bind_groups.iter() {
+                render_pass.set_bind_group(1, bind_group, &[*offset]);
+                render_pass.draw(0..6, 0..1);
+            }
+        }
+
+        // ---------------------------------------------------------------
+        // light cookie atlas, one row per cookie
+
+        if let Some(cookie_binding) = light_cookies.buffer.binding() {
+            let cookie_bind_groups = light_cookies
+                .images
+                .iter()
+                .map(|image| {
+                    // a white row lets the light through until its image is loaded
+                    let image_view = image
+                        .and_then(|image| gpu_imges.get(image))
+                        .map(|image| &image.texture_view)
+                        .unwrap_or(&fallback_image.d2.texture_view);
+
+                    render_context.render_device().create_bind_group(
+                        Some("cookie_bind_group"),
+                        &cookie_pipeline.layout,
+                        &BindGroupEntries::sequential((
+                            image_view,
+                            &cookie_pipeline.sampler,
+                            cookie_binding.clone(),
+                        )),
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+                label: Some("cookie_pass"),
                 color_attachments: &[Some(RenderPassColorAttachment {
-                    view: &radiance_targets.mipmap.default_view,
+                    view: &cookie_atlas.default_view,
                     resolve_target: None,
                     ops: Operations::default(),
                 })],
@@ -217,11 +379,432 @@ impl render_graph::ViewNode for LightNode {
                 occlusion_query_set: None,
             });
 
-            render_pass.set_render_pipeline(mipmap_render_pipeline);
-            render_pass.set_bind_group(0, &mipmap_bind_group, &[]);
+            render_pass.set_render_pipeline(cookie_render_pipeline);
+            let width = cookie_atlas.texture.width() as f32;
+            for (row, bind_group) in cookie_bind_groups.iter().enumerate() {
+                render_pass.set_viewport(0., row as f32, width, 1., 0., 1.);
+                render_pass.set_bind_group(0, bind_group, &[]);
+                render_pass.draw(0..3, row as u32..row as u32 + 1);
+            }
+        }
+
+        // ---------------------------------------------------------------
+        // jump flood, seeds from the mask edges
+
+        let seed_bind_group = render_context.render_device().create_bind_group(
+            Some("seed_bind_group"),
+            &jfa_pipeline.seed_layout,
+            &BindGroupEntries::single(&radiance_targets.mask.default_view),
+        );
+        {
+            let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+                label: Some("seed_pass"),
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view: &radiance_targets.jfa0.default_view,
+                    resolve_target: None,
+                    ops: Operations::default(),
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+
+            render_pass.set_render_pipeline(seed_render_pipeline);
+            render_pass.set_bind_group(0, &seed_bind_group, &[]);
             render_pass.draw(0..3, 0..1);
         }
 
+        // without sprites or layers there is nothing to flood, the seed
+        // pass already marked every texel as empty.
+        let flood_steps = match sprite_bind_groups.is_empty() && layer_bind_group.is_none() {
+            true => 0,
+            false => radiance_buffers.jfa_offsets.len(),
+        };
+
+        if let Some(jfa_binding) = radiance_buffers.jfa_buffer.binding() {
+            for i in 0..flood_steps {
+                let (current_target, last_target) = if i % 2 == 0 {
+                    (
+                        &radiance_targets.jfa1.default_view,
+                        &radiance_targets.jfa0.default_view,
+                    )
+                } else {
+                    (
+                        &radiance_targets.jfa0.default_view,
+                        &radiance_targets.jfa1.default_view,
+                    )
+                };
+
+                let flood_bind_group = render_context.render_device().create_bind_group(
+                    Some("flood_bind_group"),
+                    &jfa_pipeline.flood_layout,
+                    &BindGroupEntries::sequential((last_target, jfa_binding.clone())),
+                );
+
+                let mut render_pass =
+                    render_context.begin_tracked_render_pass(RenderPassDescriptor {
+                        label: Some("flood_pass"),
+                        color_attachments: &[Some(RenderPassColorAttachment {
+                            view: current_target,
+                            resolve_target: None,
+                            ops: Operations::default(),
+                        })],
+                        depth_stencil_attachment: None,
+                        timestamp_writes: None,
+                        occlusion_query_set: None,
+                    });
+
+                render_pass.set_render_pipeline(flood_render_pipeline);
+                render_pass.set_bind_group(
+                    0,
+                    &flood_bind_group,
+                    &[radiance_buffers.jfa_offsets[i]],
+                );
+                render_pass.draw(0..3, 0..1);
+            }
+        }
+
+        let jfa_result = if flood_steps % 2 == 0 {
+            &radiance_targets.jfa0.default_view
+        } else {
+            &radiance_targets.jfa1.default_view
+        };
+
+        // ---------------------------------------------------------------
+        // create sdf texture once, then the emission and cascades of each light channel
+
+        let sdf_bind_group = match compute {
+            // the compute pass writes its targets as storage textures
+            Some((compute_pipeline, ..)) => render_context.render_device().create_bind_group(
+                Some("sdf_compute_bind_group"),
+                &compute_pipeline.sdf_layout,
+                &BindGroupEntries::sequential((
+                    view_uniform_binding.clone(),
+                    sdf_circle_binding.clone(),
+                    sdf_rect_binding.clone(),
+                    sdf_polygon_binding.clone(),
+                    sdf_vertex_binding.clone(),
+                    sdf_segment_binding.clone(),
+                    sdf_ellipse_binding.clone(),
+                    gi_config_binding.clone(),
+                    jfa_result,
+                    &radiance_targets.mask.default_view,
+                    sdf_tile_binding,
+                    sdf_material_binding.clone(),
+                    &radiance_targets.emission.default_view,
+                    &radiance_targets.sdf.default_view,
+                    &radiance_targets.occluder.default_view,
+                    &radiance_targets.absorption.default_view,
+                    &radiance_targets.shape_ids.default_view,
+                )),
+            ),
+            None => render_context.render_device().create_bind_group(
+                Some("sdf_bind_group"),
+                &sdf_pipeline.layout,
+                &BindGroupEntries::sequential((
+                    view_uniform_binding.clone(),
+                    sdf_circle_binding.clone(),
+                    sdf_rect_binding.clone(),
+                    sdf_polygon_binding.clone(),
+                    sdf_vertex_binding.clone(),
+                    sdf_segment_binding.clone(),
+                    sdf_ellipse_binding.clone(),
+                    gi_config_binding.clone(),
+                    jfa_result,
+                    &radiance_targets.mask.default_view,
+                    sdf_tile_binding,
+                    sdf_material_binding.clone(),
+                    &radiance_targets.emission.default_view,
+                )),
+            ),
+        };
+
+        let scaled_size = radiance_targets.sdf.texture.size();
+        let scaled_workgroups = workgroups(UVec2::new(scaled_size.width, scaled_size.height));
+
+        if let Some((_, sdf_compute_pipeline, _)) = compute {
+            let mut compute_pass =
+                render_context
+                    .command_encoder()
+                    .begin_compute_pass(&ComputePassDescriptor {
+                        label: Some("sdf_compute_pass"),
+                        timestamp_writes: None,
+                    });
+
+            compute_pass.set_pipeline(sdf_compute_pipeline);
+            compute_pass.set_bind_group(0, &sdf_bind_group, &[view_offset.offset]);
+            compute_pass.dispatch_workgroups(scaled_workgroups.x, scaled_workgroups.y, 1);
+        } else {
+            let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+                label: Some("sdf_pass"),
+                color_attachments: &[
+                    Some(RenderPassColorAttachment {
+                        view: &radiance_targets.sdf.default_view,
+                        resolve_target: None,
+                        ops: Operations::default(),
+                    }),
+                    Some(RenderPassColorAttachment {
+                        view: &radiance_targets.occluder.default_view,
+                        resolve_target: None,
+                        ops: Operations::default(),
+                    }),
+                    Some(RenderPassColorAttachment {
+                        view: &radiance_targets.absorption.default_view,
+                        resolve_target: None,
+                        ops: Operations::default(),
+                    }),
+                    Some(RenderPassColorAttachment {
+                        view: &radiance_targets.shape_ids.default_view,
+                        resolve_target: None,
+                        ops: Operations::default(),
+                    }),
+                ],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+
+            render_pass.set_render_pipeline(sdf_render_pipeline);
+            render_pass.set_bind_group(0, &sdf_bind_group, &[view_offset.offset]);
+            render_pass.draw(0..3, 0..1);
+        }
+
+        let sdf_emission_bind_group = render_context.render_device().create_bind_group(
+            Some("sdf_emission_bind_group"),
+            &sdf_pipeline.emission_layout,
+            &BindGroupEntries::with_indices((
+                (0, view_uniform_binding.clone()),
+                (1, sdf_circle_binding),
+                (2, sdf_rect_binding),
+                (3, sdf_polygon_binding),
+                (4, sdf_vertex_binding),
+                (5, sdf_segment_binding),
+                (6, sdf_ellipse_binding),
+                (7, gi_config_binding.clone()),
+                (8, jfa_result),
+                (9, &radiance_targets.mask.default_view),
+                (11, sdf_material_binding),
+                (12, &radiance_targets.emission.default_view),
+                (17, &radiance_history.light.default_view),
+                (18, probe_binding.clone()),
+                (19, &radiance_targets.sdf.default_view),
+                (20, &radiance_targets.shape_ids.default_view),
+            )),
+        );
+
+        for (channel, probe_offsets) in radiance_buffers.probe_offsets.iter() {
+            // emission of the channel's lights and the bounce of its last frame
+            {
+                let mut render_pass =
+                    render_context.begin_tracked_render_pass(RenderPassDescriptor {
+                        label: Some("sdf_emission_pass"),
+                        color_attachments: &[
+                            Some(RenderPassColorAttachment {
+                                view: &radiance_targets.channel_sdf.default_view,
+                                resolve_target: None,
+                                ops: Operations::default(),
+                            }),
+                            Some(RenderPassColorAttachment {
+                                view: &radiance_targets.spot.default_view,
+                                resolve_target: None,
+                                ops: Operations::default(),
+                            }),
+                        ],
+                        depth_stencil_attachment: None,
+                        timestamp_writes: None,
+                        occlusion_query_set: None,
+                    });
+
+                render_pass.set_render_pipeline(sdf_emission_render_pipeline);
+                render_pass.set_bind_group(
+                    0,
+                    &sdf_emission_bind_group,
+                    &[view_offset.offset, probe_offsets[0]],
+                );
+                render_pass.draw(0..3, 0..1);
+            }
+
+            // ---------------------------------------------------------------
+            // ping pong cascades
+            for (i, offset) in probe_offsets.iter().enumerate() {
+                let (current_target, last_target) = if i % 2 == 0 {
+                    (
+                        &radiance_targets.merge1.default_view,
+                        &radiance_targets.merge0.default_view,
+                    )
+                } else {
+                    (
+                        &radiance_targets.merge0.default_view,
+                        &radiance_targets.merge1.default_view,
+                    )
+                };
+
+                if let Some((compute_pipeline, _, cascade_compute_pipeline)) = compute {
+                    let cascade_bind_group = render_context.render_device().create_bind_group(
+                        Some("cascade_compute_bind_group"),
+                        &compute_pipeline.cascade_layout,
+                        &BindGroupEntries::sequential((
+                            &radiance_targets.channel_sdf.default_view,
+                            &radiance_targets.occluder.default_view,
+                            &radiance_targets.absorption.default_view,
+                            &radiance_targets.spot.default_view,
+                            last_target,
+                            normal_view,
+                            environment_view,
+                            &cookie_atlas.default_view,
+                            &radiance_pipline.radiance_sampler,
+                            gi_config_binding.clone(),
+                            probe_binding.clone(),
+                            current_target,
+                        )),
+                    );
+
+                    let mut compute_pass = render_context.command_encoder().begin_compute_pass(
+                        &ComputePassDescriptor {
+                            label: Some("cascade_compute_pass"),
+                            timestamp_writes: None,
+                        },
+                    );
+
+                    let cascade_index = config.cascade_count - 1 - i as u32;
+                    let cascade_workgroups =
+                        cascade_workgroups(radiance_buffers.cascade_size, cascade_index);
+                    compute_pass.set_pipeline(cascade_compute_pipeline);
+                    compute_pass.set_bind_group(0, &cascade_bind_group, &[*offset]);
+                    compute_pass.dispatch_workgroups(cascade_workgroups.x, cascade_workgroups.y, 1);
+                    continue;
+                }
+
+                let cascade_bind_group = render_context.render_device().create_bind_group(
+                    Some("cascade_bind_group"),
+                    &radiance_pipline.cascade_layout,
+                    &BindGroupEntries::sequential((
+                        &radiance_targets.channel_sdf.default_view,
+                        &radiance_targets.occluder.default_view,
+                        &radiance_targets.absorption.default_view,
+                        &radiance_targets.spot.default_view,
+                        last_target,
+                        normal_view,
+                        environment_view,
+                        &cookie_atlas.default_view,
+                        &radiance_pipline.radiance_sampler,
+                        gi_config_binding.clone(),
+                        probe_binding.clone(),
+                    )),
+                );
+
+                let mut render_pass =
+                    render_context.begin_tracked_render_pass(RenderPassDescriptor {
+                        label: Some("cascade_pass".into()),
+                        color_attachments: &[Some(RenderPassColorAttachment {
+                            view: current_target,
+                            resolve_target: None,
+                            ops: Operations::default(),
+                        })],
+                        depth_stencil_attachment: None,
+                        timestamp_writes: None,
+                        occlusion_query_set: None,
+                    });
+
+                render_pass.set_render_pipeline(cascade_render_pipeline);
+                render_pass.set_bind_group(0, &cascade_bind_group, &[*offset]);
+                render_pass.draw(0..3, 0..1);
+            }
+
+            // ---------------------------------------------------------------
+            // mipmap
+            let mipmap_bind_group = render_context.render_device().create_bind_group(
+                Some("mipmap_bind_group".into()),
+                &radiance_pipline.mipmap_layout,
+                &BindGroupEntries::sequential((
+                    if config.cascade_count % 2 == 0 {
+                        &radiance_targets.merge0.default_view
+                    } else {
+                        &radiance_targets.merge1.default_view
+                    },
+                    gi_config_binding.clone(),
+                )),
+            );
+            {
+                let mut render_pass =
+                    render_context.begin_tracked_render_pass(RenderPassDescriptor {
+                        label: Some("mipmap_pass".into()),
+                        color_attachments: &[Some(RenderPassColorAttachment {
+                            view: &radiance_targets.mipmap.default_view,
+                            resolve_target: None,
+                            ops: Operations::default(),
+                        })],
+                        depth_stencil_attachment: None,
+                        timestamp_writes: None,
+                        occlusion_query_set: None,
+                    });
+
+                render_pass.set_render_pipeline(mipmap_render_pipeline);
+                render_pass.set_bind_group(0, &mipmap_bind_group, &[]);
+                render_pass.draw(0..3, 0..1);
+            }
+
+            // ---------------------------------------------------------------
+            // temporal filter, blends in the reprojected history
+            let mut light = &radiance_targets.mipmap;
+            if let Some(temporal_render_pipeline) = temporal_render_pipeline {
+                let temporal_bind_group = render_context.render_device().create_bind_group(
+                    Some("temporal_bind_group"),
+                    &radiance_pipline.temporal_layout,
+                    &BindGroupEntries::sequential((
+                        &radiance_targets.mipmap.default_view,
+                        &radiance_history.light.default_view,
+                        &radiance_targets.sdf.default_view,
+                        &radiance_history.sdf.default_view,
+                        &radiance_pipline.radiance_sampler,
+                        gi_config_binding.clone(),
+                        probe_binding.clone(),
+                    )),
+                );
+
+                let mut render_pass =
+                    render_context.begin_tracked_render_pass(RenderPassDescriptor {
+                        label: Some("temporal_pass"),
+                        color_attachments: &[Some(RenderPassColorAttachment {
+                            view: &radiance_targets.temporal.default_view,
+                            resolve_target: None,
+                            ops: Operations::default(),
+                        })],
+                        depth_stencil_attachment: None,
+                        timestamp_writes: None,
+                        occlusion_query_set: None,
+                    });
+
+                render_pass.set_render_pipeline(temporal_render_pipeline);
+                render_pass.set_bind_group(0, &temporal_bind_group, &[probe_offsets[0]]);
+                render_pass.draw(0..3, 0..1);
+                light = &radiance_targets.temporal;
+            }
+
+            // composited below, bounced off occluders and blended into the next frame
+            render_context.command_encoder().copy_texture_to_texture(
+                light.texture.as_image_copy(),
+                ImageCopyTexture {
+                    texture: &radiance_history.light.texture,
+                    mip_level: 0,
+                    origin: Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: *channel,
+                    },
+                    aspect: TextureAspect::All,
+                },
+                light.texture.size(),
+            );
+        }
+
+        // scene distance for next frame's history rejection
+        render_context.command_encoder().copy_texture_to_texture(
+            radiance_targets.sdf.texture.as_image_copy(),
+            radiance_history.sdf.texture.as_image_copy(),
+            radiance_targets.sdf.texture.size(),
+        );
+
         // ---------------------------------------------------------------
         // composite
 
@@ -233,8 +816,9 @@ impl render_graph::ViewNode for LightNode {
                 &radiance_targets.sdf.default_view,
                 &radiance_targets.merge0.default_view,
                 &radiance_targets.merge1.default_view,
-                &radiance_targets.mipmap.default_view,
+                &radiance_history.light.default_view,
                 normal_view,
+                &radiance_targets.lighting_mask.default_view,
                 &radiance_pipline.radiance_sampler,
                 &radiance_pipline.point_sampler,
                 gi_config_binding,
@@ -4,7 +4,7 @@ use bevy::{
     prelude::*,
     render::{
         render_resource::{
-            binding_types::{sampler, texture_2d, uniform_buffer},
+            binding_types::{sampler, texture_2d, texture_2d_array, uniform_buffer},
             BindGroupLayout, BindGroupLayoutEntries, CachedRenderPipelineId, ColorTargetState,
             ColorWrites, FilterMode, FragmentState, MultisampleState, PipelineCache,
             PrimitiveState, RenderPipelineDescriptor, Sampler, SamplerBindingType,
@@ -22,6 +22,8 @@ pub struct RadiancePipeline {
     pub composite_layout: BindGroupLayout,
     pub mipmap_id: CachedRenderPipelineId,
     pub mipmap_layout: BindGroupLayout,
+    pub temporal_id: CachedRenderPipelineId,
+    pub temporal_layout: BindGroupLayout,
     pub radiance_sampler: Sampler,
     pub point_sampler: Sampler,
 }
@@ -32,10 +34,12 @@ impl FromWorld for RadiancePipeline {
         let cascade_layout = create_cascade_layout(&render_device);
         let composite_layout = create_composite_layout(&render_device);
         let mipmap_layout = create_mipmap_layout(&render_device);
+        let temporal_layout = create_temporal_layout(render_device);
         let server = world.resource_ref::<AssetServer>();
         let cascade_shader = server.load("embedded://solis_2d/shaders/cascade.wgsl");
         let composite_shader = server.load("embedded://solis_2d/shaders/composite.wgsl");
         let mipmap_shader = server.load("embedded://solis_2d/shaders/mipmap.wgsl");
+        let temporal_shader = server.load("embedded://solis_2d/shaders/temporal.wgsl");
         let cache = world.resource::<PipelineCache>();
 
         let cascade_id = cache.queue_render_pipeline(RenderPipelineDescriptor {
@@ -98,6 +102,26 @@ impl FromWorld for RadiancePipeline {
             }),
         });
 
+        let temporal_id = cache.queue_render_pipeline(RenderPipelineDescriptor {
+            label: Some("temporal_pipeline".into()),
+            layout: vec![temporal_layout.clone()],
+            push_constant_ranges: vec![],
+            vertex: fullscreen_shader_vertex_state(),
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState {
+                shader: temporal_shader,
+                shader_defs: vec![],
+                entry_point: "fragment".into(),
+                targets: vec![Some(ColorTargetState {
+                    format: CASCADE_FORMAT,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+        });
+
         let radiance_sampler = render_device.create_sampler(&SamplerDescriptor {
             label: Some("radiance sampler"),
             mag_filter: FilterMode::Linear,
@@ -112,6 +136,8 @@ impl FromWorld for RadiancePipeline {
             composite_id,
             mipmap_id,
             mipmap_layout,
+            temporal_id,
+            temporal_layout,
             composite_layout,
             radiance_sampler,
             point_sampler: render_device.create_sampler(&SamplerDescriptor::default()),
@@ -133,10 +159,12 @@ fn create_composite_layout(render_device: &RenderDevice) -> BindGroupLayout {
                 texture_2d(TextureSampleType::Float { filterable: true }),
                 //merge tex 1
                 texture_2d(TextureSampleType::Float { filterable: true }),
-                //mipmap tex
-                texture_2d(TextureSampleType::Float { filterable: true }),
+                // light per channel
+                texture_2d_array(TextureSampleType::Float { filterable: true }),
                 // normal
                 texture_2d(TextureSampleType::Float { filterable: true }),
+                // lighting mask
+                texture_2d(TextureSampleType::Float { filterable: true }),
                 //linear sample
                 sampler(SamplerBindingType::Filtering),
                 //point sample
@@ -155,10 +183,20 @@ fn create_cascade_layout(render_device: &RenderDevice) -> BindGroupLayout {
             (
                 // sdf
                 texture_2d(TextureSampleType::Float { filterable: true }),
+                // occluder distance
+                texture_2d(TextureSampleType::Float { filterable: true }),
+                // occluder absorption
+                texture_2d(TextureSampleType::Float { filterable: true }),
+                // spotlight facing
+                texture_2d(TextureSampleType::Float { filterable: true }),
                 // last cascade
                 texture_2d(TextureSampleType::Float { filterable: true }),
                 // normal
                 texture_2d(TextureSampleType::Float { filterable: true }),
+                // environment
+                texture_2d(TextureSampleType::Float { filterable: true }),
+                // light cookie atlas
+                texture_2d(TextureSampleType::Float { filterable: true }),
                 // radiance sampler
                 sampler(SamplerBindingType::Filtering),
                 uniform_buffer::<GiGpuConfig>(false),
@@ -181,8 +219,33 @@ fn create_mipmap_layout(render_device: &RenderDevice) -> BindGroupLayout {
     );
 }
 
+fn create_temporal_layout(render_device: &RenderDevice) -> BindGroupLayout {
+    render_device.create_bind_group_layout(
+        "temporal_layout",
+        &BindGroupLayoutEntries::sequential(
+            ShaderStages::FRAGMENT,
+            (
+                // this frame's light
+                texture_2d(TextureSampleType::Float { filterable: true }),
+                // light history per channel
+                texture_2d_array(TextureSampleType::Float { filterable: true }),
+                // sdf
+                texture_2d(TextureSampleType::Float { filterable: true }),
+                // last frame's sdf
+                texture_2d(TextureSampleType::Float { filterable: true }),
+                // radiance sampler
+                sampler(SamplerBindingType::Filtering),
+                uniform_buffer::<GiGpuConfig>(false),
+                uniform_buffer::<Probe>(true),
+            ),
+        ),
+    )
+}
+
 #[derive(ShaderType, Debug, Clone, Copy)]
 pub struct Probe {
     /// index of current
     pub cascade_index: u32,
+    /// light channel being evaluated
+    pub channel: u32,
 }
@@ -0,0 +1,122 @@
+use crate::{
+    jfa::SpriteOccluder,
+    sdf::Light,
+    sprite::{ExtractedSpriteQuad, SpriteQuad, SpriteQuadBuffers},
+    sun::DirectionalLight2d,
+};
+use bevy::{
+    prelude::*,
+    render::{view::RenderLayers, Extract},
+};
+
+/// upper limit of light channels, each one is evaluated on its own
+pub(crate) const MAX_LIGHT_CHANNELS: u32 = 4;
+
+/// Whether a sprite is lit. Sprites that do not receive light are drawn
+/// into a lighting mask and keep their own colour in the composite,
+/// neither lit nor darkened. Sprites drawn over them are left unlit too.
+#[derive(Component, Clone, Copy, Deref, DerefMut)]
+pub struct ReceivesLight(pub bool);
+
+impl Default for ReceivesLight {
+    fn default() -> Self {
+        Self(true)
+    }
+}
+
+/// Bitmask of light channels. Lights, sprite emitters and directional
+/// lights shine on their channels, the first one if they have none.
+/// Sprites are only lit by channels they share, all of them if they have
+/// none, and a sprite on no channel is not lit at all.
+///
+/// The environment always shines on the first channel. Every channel
+/// in use costs another cascade evaluation.
+#[derive(Component, Clone, Copy, Deref, DerefMut, PartialEq, Eq, Debug)]
+pub struct LightChannels(pub u32);
+
+impl LightChannels {
+    pub const NONE: Self = Self(0);
+    pub const ALL: Self = Self((1 << MAX_LIGHT_CHANNELS) - 1);
+
+    /// only the channel at `index`
+    pub const fn channel(index: u32) -> Self {
+        Self(1 << index)
+    }
+
+    /// adds the channel at `index`
+    pub const fn with(self, index: u32) -> Self {
+        Self(self.0 | 1 << index)
+    }
+}
+
+impl Default for LightChannels {
+    fn default() -> Self {
+        Self::channel(0)
+    }
+}
+
+/// channels of a light, the first one if it has none
+pub(crate) fn light_channels(channels: Option<&LightChannels>) -> u32 {
+    channels.copied().unwrap_or_default().0 & LightChannels::ALL.0
+}
+
+/// channels any light shines on, the first one is always evaluated
+#[derive(Resource, Default)]
+pub(crate) struct ActiveLightChannels(pub u32);
+
+/// everything that shines on light channels
+type ChannelLightFilter = Or<(With<Light>, With<SpriteOccluder>, With<DirectionalLight2d>)>;
+
+pub(crate) fn extract_light_channels(
+    lights: Extract<Query<&LightChannels, ChannelLightFilter>>,
+    mut active: ResMut<ActiveLightChannels>,
+) {
+    active.0 = lights.iter().fold(1, |active, channels| {
+        active | light_channels(Some(channels))
+    });
+}
+
+/// sprites with a say in the lighting mask
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct LitSpriteBuffers(pub SpriteQuadBuffers);
+
+type LitSpriteItem = (
+    Option<&'static ReceivesLight>,
+    Option<&'static LightChannels>,
+    &'static Sprite,
+    &'static Handle<Image>,
+    &'static GlobalTransform,
+    &'static InheritedVisibility,
+    Option<&'static RenderLayers>,
+);
+
+/// sprites that may opt out of some or all light
+type LitSpriteFilter = Or<(With<ReceivesLight>, With<LightChannels>)>;
+
+pub fn extract_lit_sprites(
+    sprites: Extract<Query<LitSpriteItem, LitSpriteFilter>>,
+    mut buffers: ResMut<LitSpriteBuffers>,
+) {
+    buffers.extracted.clear();
+
+    for (receives_light, channels, sprite, image, global, ihview, layers) in sprites.iter() {
+        let channels = match !matches!(receives_light, Some(ReceivesLight(false))) {
+            true => channels.copied().unwrap_or(LightChannels::ALL).0 & LightChannels::ALL.0,
+            false => LightChannels::NONE.0,
+        };
+
+        // sprites lit by every channel leave the mask alone
+        if channels == LightChannels::ALL.0 || !ihview.get() {
+            continue;
+        }
+
+        buffers.extracted.push(ExtractedSpriteQuad {
+            image: image.id(),
+            quad: SpriteQuad::new(sprite, global),
+            alpha_threshold: 0.,
+            emit: Vec3::ZERO,
+            channels,
+            layers: layers.cloned().unwrap_or_default(),
+        });
+    }
+}
@@ -1,24 +1,44 @@
-use crate::{constant::SDF_FORMAT, view::GiGpuConfig};
+use crate::{
+    buffer::SlotBuffer,
+    constant::{
+        ABSORPTION_FORMAT, OCCLUDER_FORMAT, SDF_FORMAT, SDF_TILE_SIZE, SHAPE_ID_FORMAT, SPOT_FORMAT,
+    },
+    cookie::{LightCookie, LightCookies},
+    emission::LightTexture,
+    radiance::Probe,
+    receiver::{light_channels, LightChannels},
+    view::{view_zoom, GiGpuConfig, RadianceConfig},
+};
 use bevy::{
     core_pipeline::fullscreen_vertex_shader::fullscreen_shader_vertex_state,
+    ecs::entity::EntityHashMap,
     prelude::*,
     render::{
         render_resource::{
-            binding_types::{storage_buffer_read_only, uniform_buffer},
+            binding_types::{
+                storage_buffer_read_only, texture_2d, texture_2d_array, uniform_buffer,
+            },
+            encase::internal::WriteInto,
             BindGroupLayout, BindGroupLayoutEntries, CachedRenderPipelineId, ColorTargetState,
             ColorWrites, FragmentState, MultisampleState, PipelineCache, PrimitiveState,
-            RenderPipelineDescriptor, ShaderStages, ShaderType, StorageBuffer,
+            RenderPipelineDescriptor, ShaderSize, ShaderStages, ShaderType, StorageBuffer,
+            TextureSampleType,
         },
         renderer::{RenderDevice, RenderQueue},
-        view::ViewUniform,
+        view::{ExtractedView, RenderLayers, ViewTarget, ViewUniform},
         Extract,
     },
 };
+use std::f32::consts::{FRAC_PI_2, PI};
 
+/// The sdf pass finds the closest shapes once per view, the emission
+/// pass colours them for each light channel.
 #[derive(Resource)]
 pub struct SdfPipeline {
     pub layout: BindGroupLayout,
     pub id: CachedRenderPipelineId,
+    pub emission_layout: BindGroupLayout,
+    pub emission_id: CachedRenderPipelineId,
 }
 
 impl FromWorld for SdfPipeline {
@@ -30,9 +50,62 @@ impl FromWorld for SdfPipeline {
                 ShaderStages::FRAGMENT,
                 (
                     uniform_buffer::<ViewUniform>(true),
-                    storage_buffer_read_only::<GpuCircleBuffer>(false),
-                    storage_buffer_read_only::<GpuRectBuffer>(false),
+                    storage_buffer_read_only::<Vec<GpuCirlce>>(false),
+                    storage_buffer_read_only::<Vec<GpuRect>>(false),
+                    storage_buffer_read_only::<Vec<GpuPolygon>>(false),
+                    storage_buffer_read_only::<Vec<Vec2>>(false),
+                    storage_buffer_read_only::<Vec<GpuSegment>>(false),
+                    storage_buffer_read_only::<Vec<GpuEllipse>>(false),
                     uniform_buffer::<GiGpuConfig>(false),
+                    // sprite occluder jump flood
+                    texture_2d(TextureSampleType::Float { filterable: false }),
+                    // sprite occluder mask
+                    texture_2d(TextureSampleType::Float { filterable: true }),
+                    storage_buffer_read_only::<GpuTileBuffer>(false),
+                    storage_buffer_read_only::<Vec<GpuMaterial>>(false),
+                    // textured light emission
+                    texture_2d(TextureSampleType::Float { filterable: true }),
+                ),
+            ),
+        );
+
+        // skips the tiles and the compute targets of the sdf pass
+        let emission_layout = render_device.create_bind_group_layout(
+            "sdf_emission_bindgroup",
+            &BindGroupLayoutEntries::with_indices(
+                ShaderStages::FRAGMENT,
+                (
+                    (0, uniform_buffer::<ViewUniform>(true)),
+                    (1, storage_buffer_read_only::<Vec<GpuCirlce>>(false)),
+                    (2, storage_buffer_read_only::<Vec<GpuRect>>(false)),
+                    (3, storage_buffer_read_only::<Vec<GpuPolygon>>(false)),
+                    (4, storage_buffer_read_only::<Vec<Vec2>>(false)),
+                    (5, storage_buffer_read_only::<Vec<GpuSegment>>(false)),
+                    (6, storage_buffer_read_only::<Vec<GpuEllipse>>(false)),
+                    (7, uniform_buffer::<GiGpuConfig>(false)),
+                    (
+                        8,
+                        texture_2d(TextureSampleType::Float { filterable: false }),
+                    ),
+                    (9, texture_2d(TextureSampleType::Float { filterable: true })),
+                    (11, storage_buffer_read_only::<Vec<GpuMaterial>>(false)),
+                    (
+                        12,
+                        texture_2d(TextureSampleType::Float { filterable: true }),
+                    ),
+                    // last frame's light per channel, bounced off occluders
+                    (
+                        17,
+                        texture_2d_array(TextureSampleType::Float { filterable: true }),
+                    ),
+                    // light channel, the cascades of a channel share it
+                    (18, uniform_buffer::<Probe>(true)),
+                    // distance and closest shapes of the sdf pass
+                    (
+                        19,
+                        texture_2d(TextureSampleType::Float { filterable: false }),
+                    ),
+                    (20, texture_2d(TextureSampleType::Uint)),
                 ),
             ),
         );
@@ -41,41 +114,190 @@ impl FromWorld for SdfPipeline {
         let shader = server.load("embedded://solis_2d/shaders/sdf.wgsl");
         // let shader = server.load("sdf.wgsl");
 
-        let id =
-            world
-                .resource_mut::<PipelineCache>()
-                .queue_render_pipeline(RenderPipelineDescriptor {
-                    label: Some("sdf_pipeline".into()),
-                    layout: vec![layout.clone()],
-                    push_constant_ranges: vec![],
-                    vertex: fullscreen_shader_vertex_state(),
-                    primitive: PrimitiveState::default(),
-                    depth_stencil: None,
-                    multisample: MultisampleState::default(),
-                    fragment: Some(FragmentState {
-                        shader,
-                        shader_defs: vec![],
-                        entry_point: "fragment".into(),
-                        targets: vec![Some(ColorTargetState {
-                            format: SDF_FORMAT,
-                            blend: None,
-                            write_mask: ColorWrites::ALL,
-                        })],
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let id = pipeline_cache.queue_render_pipeline(RenderPipelineDescriptor {
+            label: Some("sdf_pipeline".into()),
+            layout: vec![layout.clone()],
+            push_constant_ranges: vec![],
+            vertex: fullscreen_shader_vertex_state(),
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState {
+                shader: shader.clone(),
+                shader_defs: vec![],
+                entry_point: "fragment".into(),
+                targets: vec![
+                    Some(ColorTargetState {
+                        format: SDF_FORMAT,
+                        blend: None,
+                        write_mask: ColorWrites::ALL,
                     }),
-                });
+                    Some(ColorTargetState {
+                        format: OCCLUDER_FORMAT,
+                        blend: None,
+                        write_mask: ColorWrites::ALL,
+                    }),
+                    Some(ColorTargetState {
+                        format: ABSORPTION_FORMAT,
+                        blend: None,
+                        write_mask: ColorWrites::ALL,
+                    }),
+                    Some(ColorTargetState {
+                        format: SHAPE_ID_FORMAT,
+                        blend: None,
+                        write_mask: ColorWrites::ALL,
+                    }),
+                ],
+            }),
+        });
+
+        let emission_id = pipeline_cache.queue_render_pipeline(RenderPipelineDescriptor {
+            label: Some("sdf_emission_pipeline".into()),
+            layout: vec![emission_layout.clone()],
+            push_constant_ranges: vec![],
+            vertex: fullscreen_shader_vertex_state(),
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState {
+                shader,
+                shader_defs: vec![],
+                entry_point: "emission".into(),
+                targets: vec![
+                    Some(ColorTargetState {
+                        format: SDF_FORMAT,
+                        blend: None,
+                        write_mask: ColorWrites::ALL,
+                    }),
+                    Some(ColorTargetState {
+                        format: SPOT_FORMAT,
+                        blend: None,
+                        write_mask: ColorWrites::ALL,
+                    }),
+                ],
+            }),
+        });
 
-        Self { layout, id }
+        Self {
+            layout,
+            id,
+            emission_layout,
+            emission_id,
+        }
     }
 }
 
 // ---------------------------
 // extract
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub enum SdfShape {
     Circle(f32),
     Rect(Vec2),
+    /// local space vertices, convex or concave, without self intersections
+    Polygon(Vec<Vec2>),
+    /// rounded line along the local x axis
+    Capsule {
+        half_length: f32,
+        radius: f32,
+    },
+    /// line between two local points
+    Segment {
+        a: Vec2,
+        b: Vec2,
+        thickness: f32,
+    },
+    /// rect with rounded corners
+    RoundedRect {
+        half_extends: Vec2,
+        corner_radius: f32,
+    },
+    /// ellipse radii along the local x and y axis
+    Ellipse(Vec2),
+    /// annulus between the inner and outer radius
+    Ring {
+        inner: f32,
+        outer: f32,
+    },
 }
 
+/// Emits light inside its shape. On its own a light does not block
+/// other light, pair it with an `Occluder` for that.
+#[derive(Component, Clone)]
+pub struct Light {
+    pub intensity: f32,
+    pub color: Color,
+    pub shape: SdfShape,
+    /// height above the ground, `RadianceConfig::light_z` if `None`
+    pub height: Option<f32>,
+}
+
+/// Blocks light inside its shape.
+#[derive(Component, Clone)]
+pub struct Occluder {
+    pub shape: SdfShape,
+    /// height above the ground, infinitely tall if `None`.
+    /// Lights placed higher cast shorter shadows past it.
+    pub height: Option<f32>,
+    /// fraction of light absorbed per world unit travelled inside,
+    /// per linear colour channel. Blocks all light if `None`.
+    /// Translucent occluders ignore their height.
+    pub absorption: Option<LinearRgba>,
+}
+
+impl Occluder {
+    /// beer lambert coefficients per world unit, `None` if opaque
+    fn absorption_coefficients(&self) -> Option<Vec3> {
+        let absorption = self.absorption?.to_vec3();
+        let transmission = (Vec3::ONE - absorption).clamp(Vec3::splat(1e-4), Vec3::ONE);
+        Some(Vec3::from_array(transmission.to_array().map(|t| -t.ln())))
+    }
+}
+
+/// Diffuse colour of an `Occluder`, lit occluders reflect
+/// `RadianceConfig::bounce` times this of their incoming light.
+#[derive(Component, Clone, Copy, Deref, DerefMut)]
+pub struct Albedo(pub Color);
+
+/// Narrows a `Light` to a cone along its local x axis, like a
+/// flashlight or street lamp. Angles are measured from the axis in radians,
+/// the light fades out between `inner_angle` and `outer_angle`.
+#[derive(Component, Clone, Copy)]
+pub struct Spotlight {
+    pub inner_angle: f32,
+    pub outer_angle: f32,
+}
+
+impl Default for Spotlight {
+    fn default() -> Self {
+        Self {
+            inner_angle: 0.3,
+            outer_angle: 0.5,
+        }
+    }
+}
+
+/// Limits how far a `Light` reaches, in world units. Without it light
+/// travels as far as the cascades reach.
+#[derive(Component, Clone, Copy)]
+pub enum Falloff {
+    /// fades to nothing at `range`, higher exponents fade sooner
+    Range { range: f32, exponent: f32 },
+    /// physical falloff past `radius`, cut off smoothly at `range`
+    InverseSquare { radius: f32, range: f32 },
+}
+
+impl Default for Falloff {
+    fn default() -> Self {
+        Self::Range {
+            range: 200.,
+            exponent: 1.,
+        }
+    }
+}
+
+/// Emits light and occludes with the same shape.
+/// Inserts and updates a `Light` and an `Occluder` on the entity.
 #[derive(Component, Clone)]
 pub struct Emitter {
     pub intensity: f32,
@@ -83,6 +305,51 @@ pub struct Emitter {
     pub shape: SdfShape,
 }
 
+/// Keeps an `Emitter` from blocking other light, it only shines.
+#[derive(Component, Default, Clone)]
+pub struct NoShadow;
+
+/// emitters whose `Light` and `Occluder` are out of date
+type OutdatedEmitterFilter = (With<Emitter>, Or<(Changed<Emitter>, Added<NoShadow>)>);
+
+/// keeps the `Light` and `Occluder` of an `Emitter` in sync
+pub(crate) fn sync_emitters(
+    emitters: Query<(&Emitter, Has<NoShadow>)>,
+    changed: Query<Entity, OutdatedEmitterFilter>,
+    mut removed: RemovedComponents<Emitter>,
+    mut shadows: RemovedComponents<NoShadow>,
+    mut cmd: Commands,
+) {
+    for entity in removed.read() {
+        if let Some(mut entity) = cmd.get_entity(entity) {
+            entity.remove::<(Light, Occluder)>();
+        }
+    }
+
+    for entity in changed.iter().chain(shadows.read()) {
+        let Ok((emitter, no_shadow)) = emitters.get(entity) else {
+            continue;
+        };
+
+        let mut entity = cmd.entity(entity);
+        entity.insert(Light {
+            intensity: emitter.intensity,
+            color: emitter.color,
+            shape: emitter.shape.clone(),
+            height: None,
+        });
+
+        match no_shadow {
+            false => entity.insert(Occluder {
+                shape: emitter.shape.clone(),
+                height: None,
+                absorption: None,
+            }),
+            true => entity.remove::<Occluder>(),
+        };
+    }
+}
+
 #[derive(Component, Default, Clone)]
 pub struct DisableEmitter;
 
@@ -91,99 +358,690 @@ pub struct GpuRect {
     half_extends: Vec2,
     center: Vec2,
     rotation: f32,
-    emit: Vec3,
-    intensity: f32,
+    /// zero for sharp corners
+    corner_radius: f32,
+    material: u32,
 }
 
 #[derive(Component, ShaderType, Debug, Clone)]
 pub struct GpuCirlce {
     radius: f32,
+    /// zero for a filled circle, anything above makes it a ring
+    inner_radius: f32,
     center: Vec2,
+    material: u32,
+}
+
+#[derive(Component, ShaderType, Debug, Clone)]
+pub struct GpuPolygon {
+    /// first vertex in the vertex buffer
+    start: u32,
+    /// vertex count
+    len: u32,
+    material: u32,
+}
+
+#[derive(Component, ShaderType, Debug, Clone)]
+pub struct GpuEllipse {
+    radii: Vec2,
+    center: Vec2,
+    rotation: f32,
+    /// inner radius relative to the radii, zero for a filled ellipse
+    inner: f32,
+    material: u32,
+}
+
+/// shared by capsules and segments
+#[derive(Component, ShaderType, Debug, Clone)]
+pub struct GpuSegment {
+    a: Vec2,
+    b: Vec2,
+    radius: f32,
+    material: u32,
+}
+
+/// light and occluder settings, shared by all shape kinds
+#[derive(ShaderType, Debug, Clone, Default)]
+pub struct GpuMaterial {
     emit: Vec3,
     intensity: f32,
+    /// negative for infinitely tall occluders
+    occluder_height: f32,
+    /// negative to use the view `light_z`
+    light_height: f32,
+    /// beer lambert coefficients per world unit of translucent occluders
+    absorption: Vec3,
+    /// 1 for translucent occluders, 0 for opaque ones
+    translucent: u32,
+    /// reflected share of the light bounced off occluders
+    albedo: Vec3,
+    /// world space facing of lights, their local x axis
+    spot_direction: Vec2,
+    /// cosine of the spotlight angles, -1 for lights shining everywhere
+    spot_inner: f32,
+    spot_outer: f32,
+    /// 1 if the emission is taken from a `LightTexture`
+    textured: u32,
+    /// cookie atlas row plus one, zero without a `LightCookie`
+    cookie: u32,
+    /// reach of the light, zero for lights without a `Falloff`
+    range: f32,
+    /// fade exponent, or the negative inverse square radius
+    falloff: f32,
+    /// light channels the light shines on
+    channels: u32,
 }
 
-#[derive(ShaderType, Default, Clone)]
-pub struct GpuCircleBuffer {
-    pub count: u32,
-    #[size(runtime)]
-    pub data: Vec<GpuCirlce>,
+impl GpuMaterial {
+    fn with_spotlight(mut self, spotlight: Option<&Spotlight>, global: &GlobalTransform) -> Self {
+        self.spot_direction = global.right().truncate().normalize_or_zero();
+        (self.spot_inner, self.spot_outer) = match spotlight {
+            Some(spotlight) => {
+                let inner = spotlight.inner_angle.clamp(0., PI);
+                (inner.cos(), spotlight.outer_angle.clamp(inner, PI).cos())
+            }
+            None => (-1., -1.),
+        };
+        self
+    }
+
+    fn with_falloff(mut self, falloff: Option<&Falloff>) -> Self {
+        (self.range, self.falloff) = match falloff {
+            Some(Falloff::Range { range, exponent }) => (range.max(0.), exponent.max(0.01)),
+            Some(Falloff::InverseSquare { radius, range }) => (range.max(0.), -radius.max(1e-3)),
+            None => (0., 0.),
+        };
+        self
+    }
+
+    fn with_occluder(mut self, occluder: Option<&Occluder>, albedo: Option<&Albedo>) -> Self {
+        let Some(occluder) = occluder else {
+            self.occluder_height = -1.;
+            return self;
+        };
+        self.occluder_height = occluder.height.unwrap_or(-1.);
+        if let Some(absorption) = occluder.absorption_coefficients() {
+            self.absorption = absorption;
+            self.translucent = 1;
+        }
+        if let Some(albedo) = albedo {
+            self.albedo = albedo.to_linear().to_vec3();
+        }
+        self
+    }
 }
 
-#[derive(ShaderType, Default, Clone)]
-pub struct GpuRectBuffer {
-    pub count: u32,
-    #[size(runtime)]
-    pub data: Vec<GpuRect>,
+/// buffer slot of an extracted shape
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct EmitterSlot {
+    kind: u32,
+    index: u32,
+    /// `SHAPE_LIGHT` and `SHAPE_OCCLUDER` bits
+    roles: u32,
+    material: u32,
 }
 
-#[derive(Resource, Default)]
+/// slots of an entity, a light and occluder with the
+/// same shape share the light slot.
+#[derive(Clone, Default)]
+pub struct EmitterSlots {
+    light: Option<EmitterSlot>,
+    occluder: Option<EmitterSlot>,
+    /// views only see shapes on one of their layers
+    layers: RenderLayers,
+}
+
+impl EmitterSlots {
+    fn iter(&self) -> impl Iterator<Item = &EmitterSlot> {
+        self.light.iter().chain(self.occluder.iter())
+    }
+}
+
+#[derive(Resource)]
 pub struct SdfBuffers {
-    pub circle_buffer: StorageBuffer<GpuCircleBuffer>,
-    pub rect_buffer: StorageBuffer<GpuRectBuffer>,
+    pub circle_buffer: SlotBuffer<GpuCirlce>,
+    pub rect_buffer: SlotBuffer<GpuRect>,
+    pub polygon_buffer: SlotBuffer<GpuPolygon>,
+    /// world space polygon vertices
+    pub vertex_buffer: SlotBuffer<Vec2>,
+    pub segment_buffer: SlotBuffer<GpuSegment>,
+    pub ellipse_buffer: SlotBuffer<GpuEllipse>,
+    pub material_buffer: SlotBuffer<GpuMaterial>,
+    pub slots: EntityHashMap<EmitterSlots>,
+    /// vertices no longer referenced by any polygon
+    wasted_vertices: usize,
+}
+
+impl Default for SdfBuffers {
+    fn default() -> Self {
+        Self {
+            circle_buffer: SlotBuffer::new("sdf_circle_buffer"),
+            rect_buffer: SlotBuffer::new("sdf_rect_buffer"),
+            polygon_buffer: SlotBuffer::new("sdf_polygon_buffer"),
+            vertex_buffer: SlotBuffer::new("sdf_vertex_buffer"),
+            segment_buffer: SlotBuffer::new("sdf_segment_buffer"),
+            ellipse_buffer: SlotBuffer::new("sdf_ellipse_buffer"),
+            material_buffer: SlotBuffer::new("sdf_material_buffer"),
+            slots: EntityHashMap::default(),
+            wasted_vertices: 0,
+        }
+    }
+}
+
+enum GpuShape {
+    Circle(GpuCirlce),
+    Rect(GpuRect),
+    Polygon(Vec<Vec2>, GpuPolygon),
+    Segment(GpuSegment),
+    Ellipse(GpuEllipse),
+}
+
+/// an extracted shape with its roles and material
+struct ExtractedShape {
+    shape: GpuShape,
+    roles: u32,
+    material: GpuMaterial,
+}
+
+impl GpuShape {
+    fn set_material(&mut self, material: u32) {
+        match self {
+            GpuShape::Circle(circle) => circle.material = material,
+            GpuShape::Rect(rect) => rect.material = material,
+            GpuShape::Polygon(_, polygon) => polygon.material = material,
+            GpuShape::Segment(segment) => segment.material = material,
+            GpuShape::Ellipse(ellipse) => ellipse.material = material,
+        }
+    }
+
+    fn kind(&self) -> u32 {
+        match self {
+            GpuShape::Circle(_) => SHAPE_CIRCLE,
+            GpuShape::Rect(_) => SHAPE_RECT,
+            GpuShape::Polygon(..) => SHAPE_POLYGON,
+            GpuShape::Segment(_) => SHAPE_SEGMENT,
+            GpuShape::Ellipse(_) => SHAPE_ELLIPSE,
+        }
+    }
 }
 
+impl SdfBuffers {
+    /// writes the light and occluder shape of an entity into its slots
+    fn upsert(
+        &mut self,
+        entity: Entity,
+        layers: RenderLayers,
+        light: Option<ExtractedShape>,
+        occluder: Option<ExtractedShape>,
+    ) {
+        let slots = self.slots.remove(&entity).unwrap_or_default();
+        let slots = EmitterSlots {
+            light: self.write(slots.light, light),
+            occluder: self.write(slots.occluder, occluder),
+            layers,
+        };
+
+        if slots.light.is_some() || slots.occluder.is_some() {
+            self.slots.insert(entity, slots);
+        }
+    }
+
+    /// writes the shape into the slot, reusing it when the kind matches
+    fn write(
+        &mut self,
+        slot: Option<EmitterSlot>,
+        shape: Option<ExtractedShape>,
+    ) -> Option<EmitterSlot> {
+        let Some(ExtractedShape {
+            mut shape,
+            roles,
+            material,
+        }) = shape
+        else {
+            if let Some(slot) = slot {
+                self.free(slot);
+            }
+            return None;
+        };
+
+        let material = upsert_slot(
+            &mut self.material_buffer,
+            slot.map(|slot| slot.material),
+            material,
+        );
+        shape.set_material(material);
+
+        let kind = shape.kind();
+        if let Some(slot) = slot.filter(|slot| slot.kind != kind) {
+            self.free_shape(slot);
+        }
+
+        let existing = slot.filter(|slot| slot.kind == kind).map(|slot| slot.index);
+        let index = match shape {
+            GpuShape::Circle(circle) => upsert_slot(&mut self.circle_buffer, existing, circle),
+            GpuShape::Rect(rect) => upsert_slot(&mut self.rect_buffer, existing, rect),
+            GpuShape::Segment(segment) => upsert_slot(&mut self.segment_buffer, existing, segment),
+            GpuShape::Ellipse(ellipse) => upsert_slot(&mut self.ellipse_buffer, existing, ellipse),
+            GpuShape::Polygon(vertices, mut polygon) => {
+                let previous = existing.map(|index| self.polygon_buffer.get(index));
+                match previous {
+                    // same vertex count, rewrite in place
+                    Some(previous) if previous.len as usize == vertices.len() => {
+                        polygon.start = previous.start;
+                        for (i, vertex) in vertices.into_iter().enumerate() {
+                            self.vertex_buffer.set(polygon.start + i as u32, vertex);
+                        }
+                    }
+                    previous => {
+                        self.wasted_vertices += previous.map(|p| p.len as usize).unwrap_or(0);
+                        polygon.start = self.vertex_buffer.extend(vertices).start;
+                    }
+                }
+                upsert_slot(&mut self.polygon_buffer, existing, polygon)
+            }
+        };
+
+        Some(EmitterSlot {
+            kind,
+            index,
+            roles,
+            material,
+        })
+    }
+
+    fn remove(&mut self, entity: Entity) {
+        if let Some(slots) = self.slots.remove(&entity) {
+            slots.iter().for_each(|slot| self.free(*slot));
+        }
+    }
+
+    fn free(&mut self, slot: EmitterSlot) {
+        self.material_buffer.remove(slot.material);
+        self.free_shape(slot);
+    }
+
+    fn free_shape(&mut self, slot: EmitterSlot) {
+        match slot.kind {
+            SHAPE_CIRCLE => self.circle_buffer.remove(slot.index),
+            SHAPE_RECT => self.rect_buffer.remove(slot.index),
+            SHAPE_SEGMENT => self.segment_buffer.remove(slot.index),
+            SHAPE_ELLIPSE => self.ellipse_buffer.remove(slot.index),
+            _ => {
+                self.wasted_vertices += self.polygon_buffer.get(slot.index).len as usize;
+                self.polygon_buffer.remove(slot.index);
+            }
+        }
+    }
+
+    /// rebuilds the vertex buffer once most of it is unused
+    fn compact_vertices(&mut self) {
+        if self.wasted_vertices * 2 <= self.vertex_buffer.len() {
+            return;
+        }
+
+        let mut vertices = Vec::with_capacity(self.vertex_buffer.len() - self.wasted_vertices);
+        for slot in self
+            .slots
+            .values()
+            .flat_map(EmitterSlots::iter)
+            .filter(|slot| slot.kind == SHAPE_POLYGON)
+        {
+            let mut polygon = self.polygon_buffer.get(slot.index).clone();
+            let range = polygon.start as usize..(polygon.start + polygon.len) as usize;
+            polygon.start = vertices.len() as u32;
+            vertices.extend_from_slice(&self.vertex_buffer.values()[range]);
+            self.polygon_buffer.set(slot.index, polygon);
+        }
+
+        self.vertex_buffer.replace(vertices);
+        self.wasted_vertices = 0;
+    }
+}
+
+fn upsert_slot<T: ShaderSize + WriteInto>(
+    buffer: &mut SlotBuffer<T>,
+    slot: Option<u32>,
+    value: T,
+) -> u32 {
+    match slot {
+        Some(slot) => {
+            buffer.set(slot, value);
+            slot
+        }
+        None => buffer.insert(value),
+    }
+}
+
+/// lights and occluders with any component that ends up in their slots changed
+type ChangedEmitterFilter = (
+    Or<(With<Light>, With<Occluder>)>,
+    Or<(
+        Changed<Light>,
+        Changed<Occluder>,
+        Changed<Albedo>,
+        Changed<Spotlight>,
+        Changed<Falloff>,
+        Changed<LightChannels>,
+        Changed<LightTexture>,
+        Changed<LightCookie>,
+        Changed<GlobalTransform>,
+        Changed<InheritedVisibility>,
+        Changed<RenderLayers>,
+    )>,
+);
+
+type EmitterItem = (
+    Option<&'static Light>,
+    Option<&'static Occluder>,
+    Option<&'static Albedo>,
+    Option<&'static Spotlight>,
+    Option<&'static Falloff>,
+    Option<&'static LightChannels>,
+    Has<LightTexture>,
+    &'static GlobalTransform,
+    &'static InheritedVisibility,
+    Option<&'static RenderLayers>,
+);
+
+type DisabledEmitterFilter = (Or<(With<Light>, With<Occluder>)>, Added<DisableEmitter>);
+
+/// Only lights and occluders that changed since the last frame are
+/// written into their persistent slots, removed ones free theirs.
+#[allow(clippy::too_many_arguments)]
 pub fn extract_emitter(
-    occluders: Extract<
-        Query<
-            (
-                &Emitter,
-                &GlobalTransform,
-                &InheritedVisibility,
-                &ViewVisibility,
-            ),
-            Without<DisableEmitter>,
-        >,
-    >,
+    changed: Extract<Query<Entity, ChangedEmitterFilter>>,
+    emitters: Extract<Query<EmitterItem, Without<DisableEmitter>>>,
+    disabled: Extract<Query<Entity, DisabledEmitterFilter>>,
+    mut removed_lights: Extract<RemovedComponents<Light>>,
+    mut removed_occluders: Extract<RemovedComponents<Occluder>>,
+    mut removed_albedos: Extract<RemovedComponents<Albedo>>,
+    mut removed_spotlights: Extract<RemovedComponents<Spotlight>>,
+    mut removed_falloffs: Extract<RemovedComponents<Falloff>>,
+    mut removed_channels: Extract<RemovedComponents<LightChannels>>,
+    mut removed_textures: Extract<RemovedComponents<LightTexture>>,
+    mut removed_cookies: Extract<RemovedComponents<LightCookie>>,
+    cookies: Res<LightCookies>,
+    mut enabled: Extract<RemovedComponents<DisableEmitter>>,
+    mut removed_layers: Extract<RemovedComponents<RenderLayers>>,
     mut buffers: ResMut<SdfBuffers>,
 ) {
-    let mut sdf_rects = Vec::new();
-    let mut sdf_circles = Vec::new();
+    let entities = removed_lights
+        .read()
+        .chain(removed_occluders.read())
+        .chain(removed_albedos.read())
+        .chain(removed_spotlights.read())
+        .chain(removed_falloffs.read())
+        .chain(removed_channels.read())
+        .chain(removed_textures.read())
+        .chain(removed_cookies.read())
+        .chain(enabled.read())
+        .chain(removed_layers.read())
+        .chain(disabled.iter())
+        .chain(changed.iter());
+
+    for entity in entities {
+        // hidden, disabled or removed shapes give up their slots
+        let Ok((
+            light,
+            occluder,
+            albedo,
+            spotlight,
+            falloff,
+            channels,
+            textured,
+            global,
+            ihview,
+            layers,
+        )) = emitters.get(entity)
+        else {
+            buffers.remove(entity);
+            continue;
+        };
 
-    for (emitter, global, ihview, view) in occluders.iter() {
-        if !view.get() || !ihview.get() {
+        if !ihview.get() {
+            buffers.remove(entity);
             continue;
         }
 
-        let transform = global.compute_transform();
-        match emitter.shape {
-            SdfShape::Circle(radius) => sdf_circles.push(GpuCirlce {
-                radius,
-                center: transform.translation.truncate(),
-                emit: emitter.color.to_linear().to_vec3(),
-                intensity: emitter.intensity,
-            }),
-            SdfShape::Rect(half_extend) => {
-                let vec_a = transform.right().truncate();
-                let vec_b = Vec2::X;
-                let full_angle = vec_a.y.atan2(vec_a.x) - vec_b.y.atan2(vec_b.x);
-
-                sdf_rects.push(GpuRect {
-                    half_extends: half_extend,
-                    center: transform.translation.truncate(),
-                    rotation: full_angle,
-                    emit: emitter.color.to_linear().to_vec3(),
-                    intensity: emitter.intensity,
-                });
+        let light_shape = |light: &Light, occluder: Option<&Occluder>, roles: u32| {
+            Some(ExtractedShape {
+                shape: gpu_shape(&light.shape, global)?,
+                roles,
+                material: GpuMaterial {
+                    emit: light.color.to_linear().to_vec3(),
+                    intensity: light.intensity,
+                    light_height: light.height.unwrap_or(-1.),
+                    textured: textured as u32,
+                    cookie: cookies.cookie(entity),
+                    channels: light_channels(channels),
+                    ..default()
+                }
+                .with_occluder(occluder, albedo)
+                .with_spotlight(spotlight, global)
+                .with_falloff(falloff),
+            })
+        };
+        let occluder_shape = |occluder: &Occluder| {
+            Some(ExtractedShape {
+                shape: gpu_shape(&occluder.shape, global)?,
+                roles: SHAPE_OCCLUDER,
+                material: GpuMaterial {
+                    light_height: -1.,
+                    ..default()
+                }
+                .with_occluder(Some(occluder), albedo),
+            })
+        };
+
+        let layers = layers.cloned().unwrap_or_default();
+        match (light, occluder) {
+            (Some(light), Some(occluder)) if light.shape == occluder.shape => buffers.upsert(
+                entity,
+                layers,
+                light_shape(light, Some(occluder), SHAPE_LIGHT | SHAPE_OCCLUDER),
+                None,
+            ),
+            (light, occluder) => buffers.upsert(
+                entity,
+                layers,
+                light.and_then(|light| light_shape(light, None, SHAPE_LIGHT)),
+                occluder.and_then(occluder_shape),
+            ),
+        }
+    }
+
+    buffers.compact_vertices();
+}
+
+fn gpu_shape(shape: &SdfShape, global: &GlobalTransform) -> Option<GpuShape> {
+    // the 2d part of the world transform, including scale and skew
+    // from parent hierarchies.
+    let affine = global.affine();
+    let linear = Mat2::from_cols(
+        affine.matrix3.x_axis.truncate(),
+        affine.matrix3.y_axis.truncate(),
+    );
+    let center = affine.translation.truncate();
+
+    if linear.determinant().abs() <= f32::EPSILON {
+        return None;
+    }
+
+    let polygon = |vertices: &[Vec2]| {
+        let mut vertices: Vec<Vec2> = vertices.iter().map(|v| center + linear * *v).collect();
+
+        // edges of (almost) zero length, relative to the size of the
+        // polygon, are dropped so the shader never divides by zero.
+        let (min, max) = vertices
+            .iter()
+            .fold((Vec2::MAX, Vec2::MIN), |(min, max), v| {
+                (min.min(*v), max.max(*v))
+            });
+        let tolerance = (max - min).max_element() * 1e-5;
+        vertices.dedup_by(|a, b| a.distance(*b) <= tolerance);
+        while vertices.len() > 1 && vertices[0].distance(vertices[vertices.len() - 1]) <= tolerance
+        {
+            vertices.pop();
+        }
+
+        let len = vertices.len() as u32;
+        (len >= 3).then_some(GpuShape::Polygon(
+            vertices,
+            GpuPolygon {
+                start: 0,
+                len,
+                material: 0,
+            },
+        ))
+    };
+
+    let shape = match shape {
+        SdfShape::Circle(radius) => circle_shape(linear, center, *radius, 0.),
+        SdfShape::Ring { inner, outer } => {
+            circle_shape(linear, center, *outer, inner.clamp(0., *outer))
+        }
+        SdfShape::Rect(half_extends) => rect_shape(linear, center, *half_extends, 0.)
+            .or_else(|| polygon(&rect_outline(*half_extends, 0.)))?,
+        SdfShape::RoundedRect {
+            half_extends,
+            corner_radius,
+        } => {
+            let corner_radius = corner_radius.clamp(0., half_extends.min_element());
+            rect_shape(linear, center, *half_extends, corner_radius)
+                .or_else(|| polygon(&rect_outline(*half_extends, corner_radius)))?
+        }
+        SdfShape::Ellipse(radii) => {
+            let (radii, rotation) = ellipse_axes(linear * Mat2::from_diagonal(*radii));
+            GpuShape::Ellipse(GpuEllipse {
+                radii,
+                center,
+                rotation,
+                inner: 0.,
+                material: 0,
+            })
+        }
+        SdfShape::Polygon(vertices) => {
+            if vertices.len() < 3 {
+                return None;
             }
+
+            // vertices are moved to world space here, this covers
+            // translation, rotation, scale and skew in one go.
+            polygon(vertices)?
         }
+        SdfShape::Capsule {
+            half_length,
+            radius,
+        } => GpuShape::Segment(GpuSegment {
+            a: center + linear * Vec2::new(-*half_length, 0.),
+            b: center + linear * Vec2::new(*half_length, 0.),
+            radius: *radius * width_scale(linear, Vec2::X),
+            material: 0,
+        }),
+        SdfShape::Segment { a, b, thickness } => GpuShape::Segment(GpuSegment {
+            a: center + linear * *a,
+            b: center + linear * *b,
+            radius: *thickness * 0.5 * width_scale(linear, (*b - *a).normalize_or(Vec2::X)),
+            material: 0,
+        }),
+    };
+
+    Some(shape)
+}
+
+/// circles and rings turn into ellipses under non uniform scale
+fn circle_shape(linear: Mat2, center: Vec2, radius: f32, inner_radius: f32) -> GpuShape {
+    let (radii, rotation) = ellipse_axes(linear * radius);
+    let inner = match radius > 0. {
+        true => inner_radius / radius,
+        false => 0.,
+    };
+
+    if radii.x - radii.y <= radii.x * 1e-4 {
+        return GpuShape::Circle(GpuCirlce {
+            radius: radii.x,
+            inner_radius: inner * radii.x,
+            center,
+            material: 0,
+        });
     }
 
-    let circle_occluders = buffers.circle_buffer.get_mut();
-    circle_occluders.count = 0;
-    circle_occluders.data.clear();
-    for circle in sdf_circles.iter() {
-        circle_occluders.count += 1;
-        circle_occluders.data.push(circle.clone());
+    GpuShape::Ellipse(GpuEllipse {
+        radii,
+        center,
+        rotation,
+        inner,
+        material: 0,
+    })
+}
+
+/// a rect stays a rect as long as its axes stay orthogonal,
+/// skewed rects are left to the polygon path.
+fn rect_shape(
+    linear: Mat2,
+    center: Vec2,
+    half_extends: Vec2,
+    corner_radius: f32,
+) -> Option<GpuShape> {
+    let (x, y) = (linear.x_axis, linear.y_axis);
+    let scale = Vec2::new(x.length(), y.length());
+
+    if x.dot(y).abs() > scale.x * scale.y * 1e-4 {
+        return None;
     }
 
-    let rect_occluders = buffers.rect_buffer.get_mut();
-    rect_occluders.count = 0;
-    rect_occluders.data.clear();
-    for rect in sdf_rects.iter() {
-        rect_occluders.count += 1;
-        rect_occluders.data.push(rect.clone());
+    Some(GpuShape::Rect(GpuRect {
+        half_extends: half_extends * scale,
+        center,
+        rotation: x.y.atan2(x.x),
+        // elliptic corners are not supported, keep them inside the shape
+        corner_radius: corner_radius * scale.min_element(),
+        material: 0,
+    }))
+}
+
+/// local outline of a rect, counter clockwise, rounded corners are split into segments
+fn rect_outline(half_extends: Vec2, corner_radius: f32) -> Vec<Vec2> {
+    const CORNER_SEGMENTS: u32 = 4;
+
+    let corners = [
+        Vec2::new(1., 1.),
+        Vec2::new(-1., 1.),
+        Vec2::new(-1., -1.),
+        Vec2::new(1., -1.),
+    ];
+
+    if corner_radius <= 0. {
+        return corners.iter().map(|c| *c * half_extends).collect();
     }
+
+    let inner = half_extends - corner_radius;
+    corners
+        .iter()
+        .enumerate()
+        .flat_map(|(i, corner)| {
+            (0..=CORNER_SEGMENTS).map(move |s| {
+                let angle = (i as f32 + s as f32 / CORNER_SEGMENTS as f32) * FRAC_PI_2;
+                *corner * inner + Vec2::from_angle(angle) * corner_radius
+            })
+        })
+        .collect()
+}
+
+/// radii and rotation of the ellipse the unit circle is mapped to
+fn ellipse_axes(linear: Mat2) -> (Vec2, f32) {
+    let s = linear * linear.transpose();
+    let (a, b, c) = (s.x_axis.x, s.x_axis.y, s.y_axis.y);
+    let mean = (a + c) * 0.5;
+    let root = (((a - c) * 0.5).powi(2) + b * b).sqrt();
+
+    let radii = Vec2::new((mean + root).max(0.).sqrt(), (mean - root).max(0.).sqrt());
+    (radii, 0.5 * (2. * b).atan2(a - c))
+}
+
+/// world width of a unit wide band along the local direction
+fn width_scale(linear: Mat2, dir: Vec2) -> f32 {
+    linear.determinant().abs() / (linear * dir).length().max(f32::EPSILON)
 }
 
 pub fn prepare_sdf_buffers(
@@ -197,4 +1055,315 @@ pub fn prepare_sdf_buffers(
     prepared
         .rect_buffer
         .write_buffer(&render_device, &render_queue);
+    prepared
+        .polygon_buffer
+        .write_buffer(&render_device, &render_queue);
+    prepared
+        .vertex_buffer
+        .write_buffer(&render_device, &render_queue);
+    prepared
+        .segment_buffer
+        .write_buffer(&render_device, &render_queue);
+    prepared
+        .ellipse_buffer
+        .write_buffer(&render_device, &render_queue);
+    prepared
+        .material_buffer
+        .write_buffer(&render_device, &render_queue);
+}
+
+// ---------------------------
+// tile binning
+
+// shape kinds, stored in the upper bits of a tile item
+const SHAPE_CIRCLE: u32 = 0;
+const SHAPE_RECT: u32 = 1;
+const SHAPE_POLYGON: u32 = 2;
+const SHAPE_SEGMENT: u32 = 3;
+const SHAPE_ELLIPSE: u32 = 4;
+const SHAPE_KIND_SHIFT: u32 = 28;
+// shape roles, stored next to the kind
+const SHAPE_LIGHT: u32 = 1 << 26;
+const SHAPE_OCCLUDER: u32 = 1 << 27;
+
+/// Per view screen tiles, each listing the shapes that can be the
+/// closest one for any of its pixel.
+#[derive(ShaderType, Default, Clone)]
+pub struct GpuTileBuffer {
+    pub tile_count: UVec2,
+    pub tile_size: u32,
+    /// world space distance used where no shape is in range
+    pub max_dist: f32,
+    /// `offset, count` pairs for each tile, followed by the tile items
+    #[size(runtime)]
+    pub data: Vec<u32>,
+}
+
+/// render world views are spawned every frame,
+/// their tile buffer is kept by view entity.
+#[derive(Resource, Default)]
+pub struct SdfTiles(pub EntityHashMap<StorageBuffer<GpuTileBuffer>>);
+
+/// bounding circle of a shape, the shape distance of any point `p`
+/// is at most `|p - center| + offset`.
+struct ShapeBound<'a> {
+    item: u32,
+    layers: &'a RenderLayers,
+    center: Vec2,
+    radius: f32,
+    offset: f32,
+}
+
+fn shape_bounds(buffers: &SdfBuffers) -> Vec<ShapeBound<'_>> {
+    let vertices = buffers.vertex_buffer.values();
+
+    buffers
+        .slots
+        .values()
+        .flat_map(|slots| slots.iter().map(move |slot| (slot, &slots.layers)))
+        .map(|(slot, layers)| {
+            let item = (slot.kind << SHAPE_KIND_SHIFT) | slot.roles | slot.index;
+            match slot.kind {
+                SHAPE_CIRCLE => {
+                    let circle = buffers.circle_buffer.get(slot.index);
+                    ShapeBound {
+                        item,
+                        layers,
+                        center: circle.center,
+                        radius: circle.radius,
+                        offset: match circle.inner_radius > 0. {
+                            true => circle.inner_radius,
+                            false => -circle.radius,
+                        },
+                    }
+                }
+                SHAPE_RECT => {
+                    let rect = buffers.rect_buffer.get(slot.index);
+                    ShapeBound {
+                        item,
+                        layers,
+                        center: rect.center,
+                        radius: rect.half_extends.length(),
+                        offset: 0.,
+                    }
+                }
+                SHAPE_POLYGON => {
+                    let polygon = buffers.polygon_buffer.get(slot.index);
+                    let start = polygon.start as usize;
+                    let points = &vertices[start..start + polygon.len as usize];
+                    let min = points.iter().fold(Vec2::MAX, |acc, p| acc.min(*p));
+                    let max = points.iter().fold(Vec2::MIN, |acc, p| acc.max(*p));
+                    let radius = (max - min).length() * 0.5;
+                    ShapeBound {
+                        item,
+                        layers,
+                        center: (min + max) * 0.5,
+                        radius,
+                        offset: radius,
+                    }
+                }
+                SHAPE_SEGMENT => {
+                    let segment = buffers.segment_buffer.get(slot.index);
+                    ShapeBound {
+                        item,
+                        layers,
+                        center: (segment.a + segment.b) * 0.5,
+                        radius: segment.a.distance(segment.b) * 0.5 + segment.radius,
+                        offset: -segment.radius,
+                    }
+                }
+                _ => {
+                    let ellipse = buffers.ellipse_buffer.get(slot.index);
+                    ShapeBound {
+                        item,
+                        layers,
+                        center: ellipse.center,
+                        radius: ellipse.radii.max_element(),
+                        offset: ellipse.inner * ellipse.radii.max_element(),
+                    }
+                }
+            }
+        })
+        .collect()
+}
+
+/// Bins the shapes on the view layers into screen tiles. A shape is kept in a tile when it could
+/// be closer than both the max march distance and the closest guaranteed
+/// shape of that tile, occluders also when they could be the closest occluder,
+/// so the distance field stays exact in march range.
+pub(crate) fn prepare_sdf_tiles(
+    views: Query<(
+        Entity,
+        &ExtractedView,
+        &ViewTarget,
+        &RadianceConfig,
+        Option<&RenderLayers>,
+    )>,
+    sdf_buffers: Res<SdfBuffers>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    mut sdf_tiles: ResMut<SdfTiles>,
+) {
+    sdf_tiles.0.retain(|entity, _| views.contains(*entity));
+    let all_bounds = shape_bounds(&sdf_buffers);
+
+    views
+        .iter()
+        .for_each(|(entity, view, view_target, cfg, view_layers)| {
+            let view_layers = view_layers.cloned().unwrap_or_default();
+            let bounds = all_bounds
+                .iter()
+                .filter(|bound| bound.layers.intersects(&view_layers))
+                .collect::<Vec<_>>();
+
+            let target_size = view_target.main_texture().size();
+            let native = Vec2::new(target_size.width as f32, target_size.height as f32);
+            let tile_count = (native.as_uvec2() + SDF_TILE_SIZE - 1) / SDF_TILE_SIZE;
+
+            let world_from_view = view.world_from_view.compute_matrix();
+            let view_from_world = world_from_view.inverse();
+            let clip_from_world = view
+                .clip_from_world
+                .unwrap_or(view.clip_from_view * view_from_world);
+
+            // world to native pixel, y down like the uv
+            let to_pixel = |world: Vec2| {
+                let ndc = clip_from_world.project_point3(world.extend(0.)).truncate();
+                Vec2::new(ndc.x + 1., 1. - ndc.y) * 0.5 * native
+            };
+
+            // pixel per world unit, smallest and largest singular value
+            let linear = Mat2::from_diagonal(native * Vec2::new(0.5, -0.5))
+                * Mat2::from_cols(
+                    clip_from_world.x_axis.truncate().truncate(),
+                    clip_from_world.y_axis.truncate().truncate(),
+                );
+            let frobenius = linear.to_cols_array().iter().map(|v| v * v).sum::<f32>();
+            let det = linear.determinant();
+            let spread = (frobenius * frobenius - 4. * det * det).max(0.).sqrt();
+            let scale_max = ((frobenius + spread) * 0.5).sqrt().max(f32::EPSILON);
+            let scale_min = ((frobenius - spread) * 0.5).sqrt().max(f32::EPSILON);
+
+            // the sdf shader converts world distance to pixel with the view zoom,
+            // the longest ray of the top cascade bounds every relevant distance.
+            let zoom = view_zoom(view);
+            let top_cascade = cfg.cascade_count.saturating_sub(1) as i32;
+            let max_dist = cfg.interval * 4_f32.powi(top_cascade) * cfg.scale_factor / zoom;
+
+            let projected = bounds
+                .iter()
+                .map(|bound| to_pixel(bound.center))
+                .collect::<Vec<_>>();
+
+            // tiles a shape can reach within the max distance, shapes
+            // further out never pass the tile test below.
+            let tile_total = (tile_count.x * tile_count.y) as usize;
+            let tile_rects = bounds
+                .iter()
+                .zip(projected.iter())
+                .map(|(bound, center)| {
+                    let reach = (max_dist + bound.radius) * scale_max;
+                    let tile_size = SDF_TILE_SIZE as f32;
+                    let min = ((*center - reach) / tile_size).floor().as_ivec2();
+                    let max = ((*center + reach) / tile_size).floor().as_ivec2();
+                    let min = min.max(IVec2::ZERO);
+                    let max = max.min(tile_count.as_ivec2() - 1);
+                    min.cmple(max).all().then_some((min, max))
+                })
+                .collect::<Vec<_>>();
+
+            // shapes per tile, counted first so all candidates share one list
+            let mut starts = vec![0usize; tile_total + 1];
+            for (min, max) in tile_rects.iter().flatten() {
+                for y in min.y..=max.y {
+                    for x in min.x..=max.x {
+                        starts[(y as u32 * tile_count.x + x as u32) as usize + 1] += 1;
+                    }
+                }
+            }
+            for i in 0..tile_total {
+                starts[i + 1] += starts[i];
+            }
+            let mut filled = starts.clone();
+            let mut candidates = vec![0usize; starts[tile_total]];
+            for (shape, rect) in tile_rects.iter().enumerate() {
+                let Some((min, max)) = rect else {
+                    continue;
+                };
+                for y in min.y..=max.y {
+                    for x in min.x..=max.x {
+                        let tile = (y as u32 * tile_count.x + x as u32) as usize;
+                        candidates[filled[tile]] = shape;
+                        filled[tile] += 1;
+                    }
+                }
+            }
+
+            let mut header = Vec::with_capacity(tile_total * 2);
+            let mut items = Vec::new();
+            let mut ranges = Vec::new();
+
+            for y in 0..tile_count.y {
+                for x in 0..tile_count.x {
+                    let tile = (y * tile_count.x + x) as usize;
+                    let min = UVec2::new(x, y).as_vec2() * SDF_TILE_SIZE as f32;
+                    let max = min + SDF_TILE_SIZE as f32;
+
+                    ranges.clear();
+                    // the shader keeps the closest shape and the closest occluder,
+                    // light only shapes must not cull occluders
+                    let mut limit = max_dist;
+                    let mut occluder_limit = max_dist;
+                    for shape in &candidates[starts[tile]..starts[tile + 1]] {
+                        let (bound, center) = (bounds[*shape], projected[*shape]);
+                        let closest = center.clamp(min, max);
+                        let farthest = Vec2::new(
+                            if center.x < (min.x + max.x) * 0.5 {
+                                max.x
+                            } else {
+                                min.x
+                            },
+                            if center.y < (min.y + max.y) * 0.5 {
+                                max.y
+                            } else {
+                                min.y
+                            },
+                        );
+                        let lower = center.distance(closest) / scale_max - bound.radius;
+                        let upper = center.distance(farthest) / scale_min + bound.offset;
+
+                        let occluder = bound.item & SHAPE_OCCLUDER != 0;
+                        if lower <= limit || (occluder && lower <= occluder_limit) {
+                            limit = limit.min(upper);
+                            if occluder {
+                                occluder_limit = occluder_limit.min(upper);
+                            }
+                            ranges.push((bound.item, lower));
+                        }
+                    }
+
+                    let offset = (tile_total * 2 + items.len()) as u32;
+                    items.extend(
+                        ranges
+                            .iter()
+                            .filter(|(item, lower)| {
+                                *lower <= limit
+                                    || (item & SHAPE_OCCLUDER != 0 && *lower <= occluder_limit)
+                            })
+                            .map(|(item, _)| *item),
+                    );
+                    header.push(offset);
+                    header.push((tile_total * 2 + items.len()) as u32 - offset);
+                }
+            }
+
+            let buffer = sdf_tiles.0.entry(entity).or_default();
+            let tiles = buffer.get_mut();
+            tiles.tile_count = tile_count;
+            tiles.tile_size = SDF_TILE_SIZE;
+            tiles.max_dist = max_dist;
+            tiles.data = header;
+            tiles.data.append(&mut items);
+            buffer.write_buffer(&render_device, &render_queue);
+        });
 }
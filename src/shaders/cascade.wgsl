@@ -1,21 +1,159 @@
 #import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
 #import bevy_render::view::View
-#import solis_2d::common::{debug_merge0, Probe, debug_merge1, GiConfig, ComputedSize, random, EPSILON }
+#import solis_2d::common::{debug_merge0, Probe, debug_merge1, GiConfig, ComputedSize, random, EPSILON, MAX_HEIGHT }
 #import solis_2d::raymarch::{raymarch_probe}
 #import bevy_render::maths::{PI_2, HALF_PI}
 
 @group(0) @binding(0) var sdf_tex: texture_2d<f32>;
-@group(0) @binding(1) var last_cascade: texture_2d<f32>;
-@group(0) @binding(2) var normal_tex: texture_2d<f32>;
-@group(0) @binding(3) var rad_sampler: sampler;
-@group(0) @binding(4) var<uniform> in_cfg: GiConfig;
-@group(0) @binding(5) var<uniform> in_probe: Probe;
+@group(0) @binding(1) var occluder_tex: texture_2d<f32>;
+@group(0) @binding(2) var absorption_tex: texture_2d<f32>;
+@group(0) @binding(3) var spot_tex: texture_2d<f32>;
+@group(0) @binding(4) var last_cascade: texture_2d<f32>;
+@group(0) @binding(5) var normal_tex: texture_2d<f32>;
+@group(0) @binding(6) var environment_tex: texture_2d<f32>;
+@group(0) @binding(7) var cookie_atlas: texture_2d<f32>;
+@group(0) @binding(8) var rad_sampler: sampler;
+@group(0) @binding(9) var<uniform> in_cfg: GiConfig;
+@group(0) @binding(10) var<uniform> in_probe: Probe;
+// compute target, the fragment entry writes to an attachment instead
+@group(0) @binding(11) var cascade_out: texture_storage_2d<rgba16float, write>;
 
+// radiance picked up along a ray and the light let through by
+// translucent occluders, which tints everything behind them.
+struct Ray {
+	radiance: vec4<f32>,
+	transmittance: vec3<f32>,
+}
+
+#ifdef COMPUTE
+// last cascade around the probes of a workgroup, an 8x8 window of upper probes
+// for each of the 4 pre-averaged rays. Loaded once, merged by every thread.
+var<workgroup> upper_probes: array<array<vec4<f32>, 64>, 4>;
+// upper probe of the first window entry of each ray
+var<private> upper_window: array<vec2<i32>, 4>;
+
+// one thread per probe, the probes of a workgroup share their direction block.
+// a probe covers `probe_base` texel per side, the thread writes all of them.
+@compute @workgroup_size(8, 8, 1)
+fn compute(
+	@builtin(workgroup_id) workgroup: vec3<u32>,
+	@builtin(local_invocation_id) local: vec3<u32>,
+) {
+	let cascade_size = in_cfg.scaled / in_cfg.probe_base;
+	let sqr_angular = 1u << in_probe.cascade_index;
+	let extent = cascade_size / sqr_angular;
+	// the last block of each axis also takes the probes past the blocks
+	let tiles = (cascade_size - (sqr_angular - 1u) * extent + 7u) / 8u;
+	let block = workgroup.xy / tiles;
+	let tile = workgroup.xy % tiles * 8u;
+	let coord = block * extent + tile + local.xy;
+	let block_end = select(block * extent + extent, cascade_size, block == vec2(sqr_angular - 1u));
+
+	if in_probe.cascade_index < in_cfg.cascade_count - 1 {
+		let index = f32(block.x + block.y * sqr_angular) * 4.;
+		let angularN1 = f32(sqr_angular * 2u);
+		let extentN1 = floor(vec2<f32>(cascade_size) / angularN1);
+		let size = vec2<f32>(textureDimensions(last_cascade));
+		for (var i = 0; i < 4; i++) {
+			let preavg = index + f32(i);
+			let blockN1 = vec2(preavg % angularN1, floor(preavg / angularN1)) * extentN1;
+			// covers the merge and the bilinear fix of every probe in the workgroup
+			let window = vec2<i32>(blockN1 + min(vec2<f32>(tile / 2u), extentN1)) - 2;
+			upper_window[i] = window;
+			let upper = clamp(window + vec2<i32>(local.xy), vec2(0), vec2<i32>(cascade_size) - 1);
+			let texel = vec2<i32>((vec2<f32>(upper) + .5) * size / vec2<f32>(cascade_size));
+			upper_probes[i][local.y * 8u + local.x] = textureLoad(last_cascade, texel, 0);
+		}
+		workgroupBarrier();
+	}
+
+	if any(coord >= block_end) {
+		return;
+	}
+
+	let radiance = probe_radiance(vec2<f32>(coord));
+	let size = textureDimensions(cascade_out);
+	let texel_size = vec2<f32>(size) / vec2<f32>(cascade_size);
+	let first = vec2<u32>(max(floor(vec2<f32>(coord) * texel_size) - 1., vec2(0.)));
+	let last = min(vec2<u32>(ceil(vec2<f32>(coord + 1u) * texel_size)) + 1u, size);
+	for (var y = first.y; y < last.y; y++) {
+		for (var x = first.x; x < last.x; x++) {
+			if all(texel_probe(vec2(x, y)) == vec2<f32>(coord)) {
+				textureStore(cascade_out, vec2(x, y), radiance);
+			}
+		}
+	}
+}
+
+// probe a texel of a cascade belongs to, the same the fragment pass finds
+fn texel_probe(texel: vec2<u32>) -> vec2<f32> {
+	let cascade_size = in_cfg.scaled / in_cfg.probe_base;
+	let size = textureDimensions(cascade_out);
+	return floor(vec2<f32>(cascade_size) * ((vec2<f32>(texel) + .5) / vec2<f32>(size)));
+}
+
+// last cascade at `uv`, filtered like the radiance sampler.
+// texels of probes inside the ray's window come from the workgroup.
+fn upper_radiance(uv: vec2<f32>, ray: u32) -> vec4<f32> {
+	let size = vec2<i32>(textureDimensions(last_cascade));
+	let position = uv * vec2<f32>(size) - .5;
+	let base = floor(position);
+	let weight = position - base;
+
+	var out = vec4(0.);
+	for (var i = 0; i < 4; i++) {
+		let offset = vec2(i % 2, i / 2);
+		let texel = clamp(vec2<i32>(base) + offset, vec2(0), size - 1);
+		let local = vec2<i32>(texel_probe(vec2<u32>(texel))) - upper_window[ray];
+		var radiance: vec4<f32>;
+		if all(local >= vec2(0)) && all(local < vec2(8)) {
+			radiance = upper_probes[ray][local.y * 8 + local.x];
+		} else {
+			radiance = textureLoad(last_cascade, texel, 0);
+		}
+		let bilinear = mix(1. - weight, weight, vec2<f32>(offset));
+		out += radiance * bilinear.x * bilinear.y;
+	}
+	return out;
+}
+
+// light of a first cascade probe inside an emitter or a solid occluder.
+// all of its rays start at the probe and stop there, one sample resolves
+// them without marching. alpha is 0 for the other probes.
+fn stopped_probe(origin: vec2<f32>) -> vec4<f32> {
+	if in_probe.cascade_index != 0 {
+		return vec4(0.);
+	}
+
+	let uv = origin / vec2<f32>(textureDimensions(sdf_tex));
+	let sample = textureSampleLevel(sdf_tex, rad_sampler, uv, 0.);
+	let occluder = textureSampleLevel(occluder_tex, rad_sampler, uv, 0.);
+	let translucent = occluder.r < 0. && occluder.g < 0.;
+	let solid = occluder.r < 0. && occluder.g >= MAX_HEIGHT && !translucent;
+	let emitter = (sample.r + sample.g + sample.b) > 0. && occluder.r < 0.;
+	let stops = emitter || (solid && ( in_cfg.flags >> 5 & 0x1 ) != 1);
+	return select(vec4(0.), vec4(sample.rgb, 1.), stops);
+}
+#else
 @fragment
 fn fragment(in : FullscreenVertexOutput) -> @location(0) vec4<f32>{
+	let cascade_size = in_cfg.scaled / in_cfg.probe_base;
+	return probe_radiance(floor(vec2<f32>(cascade_size) * in.uv));
+}
+
+fn upper_radiance(uv: vec2<f32>, ray: u32) -> vec4<f32> {
+	return textureSampleLevel(last_cascade, rad_sampler, uv, 0.);
+}
+
+fn stopped_probe(origin: vec2<f32>) -> vec4<f32> {
+	return vec4(0.);
+}
+#endif
+
+// radiance of the probe direction at `coord` of the cascade, shared by both entries
+fn probe_radiance(coord: vec2<f32>) -> vec4<f32> {
 
 	let cascade_size	= in_cfg.scaled / in_cfg.probe_base;
-	let coord			= floor(vec2<f32>(cascade_size) * in.uv);
 	let sqr_angular		= pow(2.,f32(in_probe.cascade_index));
 	let extent			= floor(vec2<f32>(cascade_size) / sqr_angular);
 	let probe			= vec4(coord % extent, floor(coord / extent));
@@ -25,6 +163,7 @@ fn fragment(in : FullscreenVertexOutput) -> @location(0) vec4<f32>{
 	let origin			= (probe.xy + .5) * linear;
 	let angular			= sqr_angular * sqr_angular * 4.0;
 	let index			= (probe.z + (probe.w * sqr_angular)) * 4.0;
+	let stopped			= stopped_probe(origin);
 
 
 	var out : vec4<f32>;
@@ -34,11 +173,17 @@ fn fragment(in : FullscreenVertexOutput) -> @location(0) vec4<f32>{
 		let delta = vec2(cos(theta), -sin(theta));
 		let ray = origin + (delta * interval);
 
-		var radiance = march(ray, delta, limit);
-		out += merge(radiance, preavg, extent, probe.xy) * 0.25;
+		if stopped.a > 0. {
+			out += stopped * 0.25;
+		} else if in_cfg.bilinear_fix == 1 && in_probe.cascade_index < in_cfg.cascade_count - 1 {
+			out += merge_bilinear_fix(origin, delta, preavg, interval, limit, probe.xy) * 0.25;
+		} else {
+			let hit = march(ray, delta, interval, limit);
+			out += merge(hit, delta, preavg, extent, probe.xy) * 0.25;
+		}
 
 		if in_probe.cascade_index == 0 && (out.r + out.g + out.b) > 0. {
-			let normal_sample = textureSample(normal_tex, rad_sampler, origin/vec2<f32>( in_cfg.scaled ));
+			let normal_sample = textureSampleLevel(normal_tex, rad_sampler, origin/vec2<f32>( in_cfg.scaled ), 0.);
 			let normal = normalize(normal_sample.rgb * 2. - 1.).xyz;
 			let light_dir = normalize(vec3(delta, in_cfg.light_z));
 			let normal_dot = max(0.,dot(light_dir,normal));
@@ -49,7 +194,6 @@ fn fragment(in : FullscreenVertexOutput) -> @location(0) vec4<f32>{
 	return out;
 }
 
-
 fn march_to_positive(
 	origin: vec2<f32>,
 	delta: vec2<f32>,
@@ -60,7 +204,7 @@ fn march_to_positive(
 	for(var i = 0; i < 8; i ++){
 		var ray		= ( origin + ( delta * dst_traveled ));
 		var uv		= vec2<f32>(ray) / vec2<f32>(textureDimensions(sdf_tex));
-		var sample	= textureSample(sdf_tex, rad_sampler, uv);
+		var sample	= textureSampleLevel(sdf_tex, rad_sampler, uv, 0.);
 
 		if sample.a > 0. {
 			return uv;
@@ -75,64 +219,207 @@ fn march_to_positive(
 fn march(
 	o: vec2<f32>,
 	delta: vec2<f32>,
+	start: f32,
 	interval: f32,
-) -> vec4<f32> {
+) -> Ray {
 
 	var origin = o;
 	var dst_traveled	= 0.;
 	var ray				= ( origin + ( delta * dst_traveled ));
 	var uv				= vec2<f32>(ray) / vec2<f32>(textureDimensions(sdf_tex));
-    var sample			= textureSample(sdf_tex, rad_sampler, uv);
+    var sample			= textureSampleLevel(sdf_tex, rad_sampler, uv, 0.);
+	var occluder		= textureSampleLevel(occluder_tex, rad_sampler, uv, 0.);
+	var absorption		= textureSampleLevel(absorption_tex, rad_sampler, uv, 0.);
+
+	// lights without an occluder are picked up once, the ray keeps going
+	var radiance		= vec3(0.);
+	var in_light		= sample.a < 0. && occluder.r >= 0.;
+	if in_light {
+		radiance = sample.rgb;
+	}
+
+	// translucent occluders are passed, light behind them is absorbed
+	// by the distance travelled inside.
+	var transmittance	= vec3(1.);
+	let translucent		= occluder.r < 0. && occluder.g < 0.;
+
+	// occluders with a height are passed, a light has to be
+	// higher than the steepest of them to be seen.
+	var slope			= 0.;
+	let solid			= occluder.r < 0. && occluder.g >= MAX_HEIGHT && !translucent;
+	if occluder.r < 0. && !solid && !translucent {
+		slope = occluder.g / max(start, 1.);
+	}
 
-	let is_emitter = sign((sample.r + sample.g + sample.b)) * abs(sign(min(0., sample.a)));
+	let is_emitter = sign((sample.r + sample.g + sample.b)) * abs(sign(min(0., occluder.r)));
 
 	if is_emitter > 0. {
-		return vec4(sample.rgb, 0.0);
+		return Ray(vec4(sample.rgb, 0.0), vec3(0.));
 	}
 
 	// fix this monster
-	if sample.a < .0 && ( in_cfg.flags >> 5 & 0x1 ) == 1 {
+	if solid && ( in_cfg.flags >> 5 & 0x1 ) == 1 {
 		origin = march_to_positive(origin,delta) * vec2<f32>(textureDimensions(sdf_tex));
 	} else {
-		if sample.a < 0. {
-			return vec4(sample.rgb, 0.0);
+		if solid {
+			return Ray(vec4(sample.rgb, 0.0), vec3(0.));
 		}
 	}
-	dst_traveled += abs(sample.a);
+	let first_step = max(abs(sample.a), select(0., 1., in_light || occluder.r < 0.));
+	if translucent {
+		transmittance *= exp(-absorption.rgb * first_step);
+	}
+	dst_traveled += first_step;
 	//skip emitter
 	for(var i = 0; i < 16; i ++){
 		ray = ( origin + ( delta * dst_traveled ));
 		uv = vec2<f32>(ray) / vec2<f32>(textureDimensions(sdf_tex));
 		if uv.x < 0. || uv.y < 0. || uv.x > 1. || uv.y > 1. {
-			return vec4(0.,0.,0.,1.);
+			return escape(radiance, transmittance, slope, start + interval);
 		}
 
-        sample = textureSample(sdf_tex, rad_sampler, uv);
-		dst_traveled += abs(sample.a);
+        sample = textureSampleLevel(sdf_tex, rad_sampler, uv, 0.);
+		occluder = textureSampleLevel(occluder_tex, rad_sampler, uv, 0.);
+		absorption = textureSampleLevel(absorption_tex, rad_sampler, uv, 0.);
+
+		let dst = max(start + dst_traveled, 1.);
+		let emission = sample.rgb * light_profile(uv, delta) * falloff(dst, occluder.a, absorption.a);
+		let translucent = occluder.r < 1. && occluder.g < 0.;
+		let low = occluder.r < 1. && occluder.g < MAX_HEIGHT && !translucent;
+		if low {
+			slope = max(slope, occluder.g / dst);
+		}
+
+		if sample.a < 1. && (occluder.r >= 1. || low || translucent) {
+			// inside a see through light, a low or a translucent occluder
+			let emits = any(sample.rgb != vec3(0.));
+			let visible = occluder.b / dst > slope;
+			radiance += select(vec3(0.), emission * transmittance, emits && visible && !in_light);
+			in_light = emits;
+			let step = max(abs(sample.a), 1.);
+			if translucent {
+				transmittance *= exp(-absorption.rgb * step);
+			}
+			dst_traveled += step;
+		} else {
+			in_light = false;
+			dst_traveled += abs(sample.a);
+		}
 
 		if dst_traveled > interval {
 			break;
 		}
 
-		if sample.a < 1. {
-			return vec4(sample.rgb, 0.0);
+		if occluder.r < 1. && !low && !translucent {
+			let visible = occluder.b / dst > slope;
+			let hit = radiance + select(vec3(0.), emission * transmittance, visible);
+			return Ray(vec4(hit, 0.0), vec3(0.));
 		}
 	}
 
-	return vec4(0.,0.,0.,1.);
+	return escape(radiance, transmittance, slope, start + interval);
+}
+
+// light sent back along the ray, spotlights only shine inside their
+// cone and cookies mask by angle around the light's facing.
+fn light_profile(uv: vec2<f32>, delta: vec2<f32>) -> vec3<f32> {
+	let spot = textureSampleLevel(spot_tex, rad_sampler, uv, 0.);
+	let facing = vec2(cos(spot.x), sin(spot.x));
+	var profile = vec3(1.);
+	if spot.z > -1. {
+		profile *= smoothstep(spot.z, max(spot.y, spot.z + EPSILON), dot(facing, -delta));
+	}
+
+	let row = round(spot.w);
+	if row >= 1. {
+		// counter clockwise in world space, screen space is y down
+		let angle = atan2(delta.y, -delta.x) - atan2(-facing.y, facing.x);
+		let atlas = vec2<f32>(textureDimensions(cookie_atlas));
+		let cookie_uv = vec2(fract(angle / PI_2), (row - .5) / atlas.y);
+		profile *= textureSampleLevel(cookie_atlas, rad_sampler, cookie_uv, 0.).rgb;
+	}
+	return profile;
+}
+
+// attenuation of a light `dist` pixel away, no range means no falloff.
+// positive shapes are exponents of a fade to the range, negative
+// ones the radius of an inverse square falloff, windowed to the range.
+fn falloff(dist: f32, range: f32, shape: f32) -> f32 {
+	if range <= 0. {
+		return 1.;
+	}
+
+	let fade = saturate(1. - dist / range);
+	if shape >= 0. {
+		return pow(fade, max(shape, EPSILON));
+	}
+
+	let radius = -shape;
+	let window = saturate(1. - pow(dist / range, 4.));
+	return radius * radius / max(dist * dist, radius * radius) * window * window;
+}
+
+// upper cascades only reach past low occluders when a light at
+// `light_z` behind the interval clears them.
+fn escape(
+	radiance: vec3<f32>,
+	transmittance: vec3<f32>,
+	slope: f32,
+	end: f32,
+) -> Ray {
+	let light_z = in_cfg.light_z * in_cfg.zoom / in_cfg.scale;
+	return Ray(vec4(radiance, select(0., 1., light_z / max(end, 1.) > slope)), transmittance);
+}
+
+// environment light in the ray direction, emission is stored zoomed.
+// the environment shines on the first light channel.
+fn sky(delta: vec2<f32>) -> vec3<f32> {
+	if in_probe.channel != 0u {
+		return vec3(0.);
+	}
+	if in_cfg.sky_texture == 1 {
+		let angle = fract(atan2(-delta.y, delta.x) / PI_2);
+		return textureSampleLevel(environment_tex, rad_sampler, vec2(angle, .5), 0.).rgb * in_cfg.zoom;
+	}
+	let up = -delta.y * .5 + .5;
+	return mix(in_cfg.sky_down.rgb, in_cfg.sky_up.rgb, up) * in_cfg.zoom;
+}
+
+// directional lights the ray points at. Their light is spread over the rays
+// inside their angular size, at least one ray catches even a tiny sun.
+fn sun(delta: vec2<f32>) -> vec3<f32> {
+	let spacing = PI_2 / (pow(4., f32(in_probe.cascade_index)) * 4.);
+	var light = vec3(0.);
+	for (var i = 0u; i < in_cfg.sun_count; i++) {
+		let sun = in_cfg.suns[i];
+		if ((sun.channels >> in_probe.channel) & 1u) == 0u {
+			continue;
+		}
+		let width = max(sun.angular_size, spacing);
+		let angle = acos(clamp(dot(delta, -sun.direction), -1., 1.));
+		light += select(vec3(0.), sun.color.rgb * PI_2 / width, angle <= width * .5);
+	}
+	return light * in_cfg.zoom;
 }
 
 fn merge(
-	radiance: vec4<f32>,
+	hit: Ray,
+	delta: vec2<f32>,
 	index: f32,
 	extent: vec2<f32>,
 	probe:	vec2<f32>,
 ) -> vec4<f32> {
 
 	let size = in_cfg.scaled / in_cfg.probe_base;
+	let radiance = hit.radiance;
+
+	if radiance.a == 0.0 {
+		return vec4(radiance.rgb, 1.0);
+	}
 
-	if (radiance.a == 0.0 || in_probe.cascade_index >= in_cfg.cascade_count - 1){
-		return vec4(radiance.rgb, 1.0 - radiance.a);
+	// rays escaping the top cascade see the sky
+	if in_probe.cascade_index >= in_cfg.cascade_count - 1 {
+		return vec4(radiance.rgb + (sky(delta) + sun(delta)) * hit.transmittance, 0.0);
 	}
 
 	let angularN1 = pow(2.0, floor(f32(in_probe.cascade_index) + 1.0));
@@ -140,11 +427,54 @@ fn merge(
 	var interpN1 = vec2(index % angularN1, floor(index / angularN1)) * extentN1;
 	interpN1 += clamp((probe * 0.5) + 0.25, vec2(0.5), extentN1 - 0.5);
 
-	let radianceN1 = textureSample(
-		last_cascade,
-		rad_sampler,
-		interpN1 * (1.0 / vec2<f32>(size)),
-	);
+	let radianceN1 = upper_radiance(interpN1 * (1.0 / vec2<f32>(size)), u32(index) % 4u);
 
-	return radiance + radianceN1;
+	return radiance + radianceN1 * vec4(hit.transmittance, 1.);
+}
+
+// bilinear fix, the interval is cast towards each of the four closest
+// upper probes and their light weighted bilinearly. Rays end where the
+// upper probe's start, light no longer leaks past occluders.
+fn merge_bilinear_fix(
+	origin: vec2<f32>,
+	delta: vec2<f32>,
+	index: f32,
+	start: f32,
+	range: f32,
+	probe: vec2<f32>,
+) -> vec4<f32> {
+
+	let size = in_cfg.scaled / in_cfg.probe_base;
+	let angularN1 = pow(2.0, floor(f32(in_probe.cascade_index) + 1.0));
+	let extentN1 = floor(vec2<f32>( size )/ angularN1);
+	let blockN1 = vec2(index % angularN1, floor(index / angularN1)) * extentN1;
+	let linearN1 = f32(in_cfg.probe_base) * angularN1;
+
+	// position in upper probes, their centers are whole numbers
+	let position = probe * 0.5 - 0.25;
+	let base = floor(position);
+	let weight = position - base;
+	let ray_start = origin + delta * start;
+
+	var out = vec4(0.);
+	for (var i = 0; i < 4; i++) {
+		let offset = vec2(f32(i % 2), f32(i / 2));
+		let upper = clamp(base + offset, vec2(0.), extentN1 - 1.);
+		let ray_end = (upper + .5) * linearN1 + delta * (start + range);
+		let ray = ray_end - ray_start;
+		let ray_length = length(ray);
+		let hit = march(ray_start, ray / max(ray_length, EPSILON), start, ray_length);
+
+		var radiance = vec4(hit.radiance.rgb, 1.);
+		if hit.radiance.a != 0. {
+			// probe centers in the same uv space as merge()
+			let radianceN1 = upper_radiance((blockN1 + upper + .5) / vec2<f32>(size), u32(index) % 4u);
+			radiance = hit.radiance + radianceN1 * vec4(hit.transmittance, 1.);
+		}
+
+		let bilinear = mix(1. - weight, weight, offset);
+		out += radiance * bilinear.x * bilinear.y;
+	}
+
+	return out;
 }
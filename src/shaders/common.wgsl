@@ -3,9 +3,13 @@
 const PI:f32  = 3.14159;
 const TAU:f32 = PI * 2.;
 const EPSILON: f32 = 4.88e-04;
+// scaled pixel height of infinitely tall occluders, fits a f16
+const MAX_HEIGHT: f32 = 60000.;
 
 struct Probe {
     cascade_index: u32,
+    // light channel being evaluated
+    channel: u32,
 }
 
 struct GiConfig{
@@ -18,8 +22,31 @@ struct GiConfig{
 	flags: u32,
 	edge_highlight: f32,
 	light_z: f32,
+	layer_threshold: f32,
+	zoom: f32,
+	bounce: f32,
+	sky_texture: u32,
+	channels: u32,
+	bilinear_fix: u32,
 	absorb: vec4<f32>,
 	modulate: vec4<f32>,
+	sky_up: vec4<f32>,
+	sky_down: vec4<f32>,
+	sun_count: u32,
+	suns: array<DirectionalLight, 4>,
+	// this frame's clip space to the history's
+	reprojection: mat4x4<f32>,
+	temporal_blend: f32,
+	disocclusion_threshold: f32,
+	history_channels: u32,
+}
+
+struct DirectionalLight {
+	color: vec4<f32>,
+	// screen space direction the light travels in
+	direction: vec2<f32>,
+	angular_size: f32,
+	channels: u32,
 }
 
 fn debug_sdf(cfg: GiConfig) -> f32{
@@ -5,11 +5,12 @@
 @group(0) @binding(1) var sdf_tex: texture_2d<f32>;
 @group(0) @binding(2) var merge_tex_0: texture_2d<f32>;
 @group(0) @binding(3) var merge_tex_1: texture_2d<f32>;
-@group(0) @binding(4) var mipmap_tex: texture_2d<f32>;
+@group(0) @binding(4) var light_tex: texture_2d_array<f32>;
 @group(0) @binding(5) var normal_tex: texture_2d<f32>;
-@group(0) @binding(6) var radiance_sampler: sampler;
-@group(0) @binding(7) var point_sampler: sampler;
-@group(0) @binding(8) var<uniform> cfg: GiConfig;
+@group(0) @binding(6) var lighting_mask_tex: texture_2d<f32>;
+@group(0) @binding(7) var radiance_sampler: sampler;
+@group(0) @binding(8) var point_sampler: sampler;
+@group(0) @binding(9) var<uniform> cfg: GiConfig;
 
 @fragment
 fn fragment(in : FullscreenVertexOutput) -> @location(0) vec4<f32>{
@@ -18,13 +19,24 @@ fn fragment(in : FullscreenVertexOutput) -> @location(0) vec4<f32>{
 	let main_sample = textureSample(main_tex, point_sampler, in.uv);
 	let sdf_sample = textureSample(sdf_tex, point_sampler,in.uv);
 
-	let light = textureSample(mipmap_tex, radiance_sampler, in.uv);
+	// light of the channels each pixel is lit by
+	let mask = textureSample(lighting_mask_tex, point_sampler, in.uv);
+	var light = vec4(0.);
+	for (var channel = 0u; channel < 4u; channel++) {
+		if ((cfg.channels >> channel) & 1u) != 0u {
+			light += textureSample(light_tex, radiance_sampler, in.uv, channel) * (1. - mask[channel]);
+		}
+	}
 	let edge_intensity = 1./abs(sdf_sample.a) * cfg.edge_highlight;
 	let inside = sign(abs(max(sdf_sample.a,0.)));
 
 	out = main_sample + light + light * edge_intensity - cfg.absorb;
 	out *= cfg.modulate;
 
+	// sprites on no channel keep their colour
+	let unlit = min(min(mask.r, mask.g), min(mask.b, mask.a));
+	out = mix(out, main_sample, unlit);
+
 	out = mix(out, vec4(abs(sdf_sample.a / 20.)), debug_sdf(cfg));
 	out = mix(out, vec4(sdf_sample.rgb, 1.), debug_voronoi(cfg));
 
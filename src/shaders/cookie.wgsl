@@ -0,0 +1,53 @@
+@group(0) @binding(0) var cookie_tex: texture_2d<f32>;
+@group(0) @binding(1) var cookie_sampler: sampler;
+@group(0) @binding(2) var<storage> cookies: array<Cookie>;
+
+const COOKIE_TEXTURE: u32 = 0;
+const COOKIE_BEAMS: u32 = 1;
+
+struct Cookie {
+	kind: u32,
+	count: f32,
+	width: f32,
+}
+
+struct CookieOutput {
+	@builtin(position) position: vec4<f32>,
+	@location(0) uv: vec2<f32>,
+	@location(1) @interpolate(flat) row: u32,
+}
+
+// fullscreen triangle, the viewport limits it to the atlas row of the instance
+@vertex
+fn vertex(
+	@builtin(vertex_index) vertex_index: u32,
+	@builtin(instance_index) row: u32,
+) -> CookieOutput {
+	let uv = vec2<f32>(f32(vertex_index >> 1u), f32(vertex_index & 1u)) * 2.0;
+
+	var out: CookieOutput;
+	out.position = vec4(uv * vec2(2.0, -2.0) + vec2(-1.0, 1.0), 0.0, 1.0);
+	out.uv = uv;
+	out.row = row;
+	return out;
+}
+
+// light let through by direction, u goes once around the light
+@fragment
+fn fragment(in: CookieOutput) -> @location(0) vec4<f32> {
+	let cookie = cookies[in.row];
+	// anti aliased beam edges, one atlas texel wide
+	let edge = fwidth(in.uv.x) * cookie.count;
+
+	switch cookie.kind {
+		case COOKIE_BEAMS: {
+			let beam = fract(in.uv.x * cookie.count);
+			let lit = smoothstep(0., edge, beam) * (1. - smoothstep(cookie.width - edge, cookie.width, beam));
+			return vec4(vec3(lit), 1.);
+		}
+		default: {
+			let sample = textureSampleLevel(cookie_tex, cookie_sampler, vec2(in.uv.x, .5), 0.);
+			return vec4(sample.rgb * sample.a, 1.);
+		}
+	}
+}
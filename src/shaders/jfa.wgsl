@@ -0,0 +1,73 @@
+#import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
+
+@group(0) @binding(0) var in_tex: texture_2d<f32>;
+@group(0) @binding(1) var<uniform> in_step: JfaStep;
+
+struct JfaStep {
+	step: u32,
+}
+
+// marks texels without a seed
+const NO_SEED: vec4<f32> = vec4(-1.);
+
+// every occupied mask texel at the border of a shape becomes a seed,
+// storing its own texel center.
+@fragment
+fn seed(in : FullscreenVertexOutput) -> @location(0) vec4<f32>{
+	let size = vec2<i32>(textureDimensions(in_tex));
+	let texel = vec2<i32>(in.uv * vec2<f32>(size));
+
+	if textureLoad(in_tex, texel, 0).a <= 0. {
+		return NO_SEED;
+	}
+
+	var offsets = array<vec2<i32>, 4>(
+		vec2( 1, 0),
+		vec2(-1, 0),
+		vec2( 0, 1),
+		vec2( 0,-1),
+	);
+
+	for(var i = 0; i < 4; i ++){
+		let neighbour = clamp(texel + offsets[i], vec2(0), size - 1);
+		if textureLoad(in_tex, neighbour, 0).a <= 0. {
+			return vec4(vec2<f32>(texel) + 0.5, 0., 0.);
+		}
+	}
+
+	return NO_SEED;
+}
+
+// one jump flood iteration, keeps the closest seed of the 3x3 neighbourhood
+@fragment
+fn flood(in : FullscreenVertexOutput) -> @location(0) vec4<f32>{
+	let size = vec2<i32>(textureDimensions(in_tex));
+	let texel = vec2<i32>(in.uv * vec2<f32>(size));
+	let center = vec2<f32>(texel) + 0.5;
+	let step = i32(in_step.step);
+
+	var best = NO_SEED;
+	var best_dist = 1e+10;
+
+	for(var y = -1; y <= 1; y ++){
+		for(var x = -1; x <= 1; x ++){
+			let neighbour = texel + vec2(x, y) * step;
+			if any(neighbour < vec2(0)) || any(neighbour >= size) {
+				continue;
+			}
+
+			let seed = textureLoad(in_tex, neighbour, 0);
+			if seed.x < 0. {
+				continue;
+			}
+
+			let dist = distance(seed.xy, center);
+			if dist < best_dist {
+				best_dist = dist;
+				best = seed;
+			}
+		}
+	}
+
+	return best;
+}
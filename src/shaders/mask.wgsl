@@ -0,0 +1,98 @@
+#import bevy_render::view::View
+#import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
+#import solis_2d::common::GiConfig
+
+@group(0) @binding(0) var<uniform> view: View;
+@group(1) @binding(0) var sprite_tex: texture_2d<f32>;
+@group(1) @binding(1) var sprite_sampler: sampler;
+@group(1) @binding(2) var<uniform> sprite: SpriteQuad;
+
+// layer pipeline
+@group(0) @binding(0) var occlusion_tex: texture_2d<f32>;
+@group(0) @binding(1) var emission_tex: texture_2d<f32>;
+@group(0) @binding(2) var layer_sampler: sampler;
+@group(0) @binding(3) var<uniform> in_cfg: GiConfig;
+
+struct SpriteQuad {
+	world_from_local: mat4x4<f32>,
+	uv_min: vec2<f32>,
+	uv_max: vec2<f32>,
+	alpha_threshold: f32,
+	emit: vec3<f32>,
+	channels: u32,
+}
+
+struct VertexOutput {
+	@builtin(position) position: vec4<f32>,
+	@location(0) uv: vec2<f32>,
+}
+
+@vertex
+fn vertex(@builtin(vertex_index) index: u32) -> VertexOutput {
+	// two triangles, unit quad centered on the origin
+	var corners = array<vec2<f32>, 6>(
+		vec2(-0.5, -0.5),
+		vec2( 0.5, -0.5),
+		vec2( 0.5,  0.5),
+		vec2(-0.5, -0.5),
+		vec2( 0.5,  0.5),
+		vec2(-0.5,  0.5),
+	);
+	let corner = corners[index];
+
+	var out: VertexOutput;
+	let world = sprite.world_from_local * vec4(corner, 0., 1.);
+	out.position = view.clip_from_world * world;
+	out.uv = mix(sprite.uv_min, sprite.uv_max, vec2(corner.x + 0.5, 0.5 - corner.y));
+	return out;
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+	let sample = textureSample(sprite_tex, sprite_sampler, in.uv);
+
+	if sample.a <= sprite.alpha_threshold {
+		discard;
+	}
+
+	// alpha keeps the light channels, offset to stay an occupied texel
+	return vec4(sprite.emit, 1. + f32(sprite.channels));
+}
+
+// textured lights, tinted by the light colour
+@fragment
+fn emission(in: VertexOutput) -> @location(0) vec4<f32> {
+	let sample = textureSample(sprite_tex, sprite_sampler, in.uv);
+
+	if sample.a <= sprite.alpha_threshold {
+		discard;
+	}
+
+	return vec4(sample.rgb * sample.a * sprite.emit, 1.);
+}
+
+// coverage of each light channel the sprite is not lit by
+@fragment
+fn lighting(in: VertexOutput) -> @location(0) vec4<f32> {
+	let sample = textureSample(sprite_tex, sprite_sampler, in.uv);
+	let lit = (vec4(sprite.channels) >> vec4(0u, 1u, 2u, 3u)) & vec4(1u);
+	return select(vec4(sample.a), vec4(0.), lit == vec4(1u));
+}
+
+// render layer masks, emission wins over plain occlusion
+@fragment
+fn layers(in : FullscreenVertexOutput) -> @location(0) vec4<f32> {
+	let occlusion = textureSample(occlusion_tex, layer_sampler, in.uv);
+	let emission = textureSample(emission_tex, layer_sampler, in.uv);
+
+	// layer emission shines on the first light channel
+	if emission.a > in_cfg.layer_threshold {
+		return vec4(emission.rgb, 2.);
+	}
+
+	if occlusion.a > in_cfg.layer_threshold {
+		return vec4(0., 0., 0., 1.);
+	}
+
+	discard;
+}
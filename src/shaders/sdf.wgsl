@@ -1,86 +1,385 @@
 #import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
 #import bevy_render::view::View
-#import solis_2d::common::{ GiConfig, random, PI, TAU }
+#import solis_2d::common::{ GiConfig, Probe, random, PI, TAU, EPSILON, MAX_HEIGHT }
 
 @group(0) @binding(0) var<uniform> view: View;
-@group(0) @binding(1) var<storage> circle_occluder_buffer: CircleBuffer;
-@group(0) @binding(2) var<storage> rect_occluder_buffer: RectBuffer;
-@group(0) @binding(3) var<uniform> in_cfg: GiConfig;
+@group(0) @binding(1) var<storage> circle_occluder_buffer: array<Circle>;
+@group(0) @binding(2) var<storage> rect_occluder_buffer: array<Rect>;
+@group(0) @binding(3) var<storage> polygon_occluder_buffer: array<Polygon>;
+@group(0) @binding(4) var<storage> vertex_buffer: array<vec2<f32>>;
+@group(0) @binding(5) var<storage> segment_occluder_buffer: array<Segment>;
+@group(0) @binding(6) var<storage> ellipse_occluder_buffer: array<Ellipse>;
+@group(0) @binding(7) var<uniform> in_cfg: GiConfig;
+@group(0) @binding(8) var jfa_tex: texture_2d<f32>;
+@group(0) @binding(9) var mask_tex: texture_2d<f32>;
+@group(0) @binding(10) var<storage> tile_buffer: TileBuffer;
+@group(0) @binding(11) var<storage> material_buffer: array<Material>;
+@group(0) @binding(12) var emission_tex: texture_2d<f32>;
+// compute targets, the fragment entry writes to attachments instead
+@group(0) @binding(13) var sdf_out: texture_storage_2d<rgba16float, write>;
+@group(0) @binding(14) var occluder_out: texture_storage_2d<rgba16float, write>;
+@group(0) @binding(15) var absorption_out: texture_storage_2d<rgba16float, write>;
+@group(0) @binding(16) var ids_out: texture_storage_2d<rg32uint, write>;
+// emission pass, runs once per light channel on the shapes found above
+@group(0) @binding(17) var bounce_tex: texture_2d_array<f32>;
+@group(0) @binding(18) var<uniform> in_probe: Probe;
+@group(0) @binding(19) var sdf_tex: texture_2d<f32>;
+@group(0) @binding(20) var ids_tex: texture_2d<u32>;
 
-struct CircleBuffer {
-    count: u32,
-    data:  array<Circle>,
+const SHAPE_CIRCLE: u32 = 0;
+const SHAPE_RECT: u32 = 1;
+const SHAPE_POLYGON: u32 = 2;
+const SHAPE_SEGMENT: u32 = 3;
+const SHAPE_ELLIPSE: u32 = 4;
+const SHAPE_KIND_SHIFT: u32 = 28;
+const SHAPE_OCCLUDER: u32 = 0x08000000;
+const SHAPE_INDEX_MASK: u32 = 0x03FFFFFF;
+// shape ids without a shape, or with a sprite as the closest one
+const NO_SHAPE: u32 = 0xFFFFFFFF;
+const SPRITE_SHAPE: u32 = 0xFFFFFFFE;
+
+struct SdfOutput {
+	// emit color on any channel and distance to the closest shape
+	@location(0) sdf: vec4<f32>,
+	// distance and height of the closest occluder, negative height for translucent ones,
+	// height and range of the closest light
+	@location(1) occluder: vec4<f32>,
+	// absorption per scaled pixel of the closest occluder, falloff of the closest light
+	@location(2) absorption: vec4<f32>,
+	// material of the closest light and binned item of the closest occluder
+	@location(3) ids: vec2<u32>,
+}
+
+struct EmissionOutput {
+	// emit color on the evaluated channel and distance to the closest shape
+	@location(0) sdf: vec4<f32>,
+	// screen space facing angle, spotlight cone cosines and cookie row of the closest light
+	@location(1) spot: vec4<f32>,
+}
+
+struct Material {
+	emit: vec3<f32>,
+	intensity: f32,
+	occluder_height: f32,
+	light_height: f32,
+	absorption: vec3<f32>,
+	translucent: u32,
+	albedo: vec3<f32>,
+	spot_direction: vec2<f32>,
+	spot_inner: f32,
+	spot_outer: f32,
+	textured: u32,
+	cookie: u32,
+	range: f32,
+	falloff: f32,
+	channels: u32,
+}
+
+struct ShapeHit {
+	dist: f32,
+	material: u32,
 }
 
-struct RectBuffer {
-    count: u32,
-    data:  array<Rect>,
+struct TileBuffer {
+	tile_count: vec2<u32>,
+	tile_size: u32,
+	max_dist: f32,
+	data: array<u32>,
 }
 
 struct Circle{
 	radius: f32,
+	inner_radius: f32,
 	center: vec2<f32>,
-	emit: vec3<f32>,
-	intensity: f32,
+	material: u32,
 }
 
 struct Rect{
 	half_extends: vec2<f32>,
 	center: vec2<f32>,
 	rotation: f32,
-	emit: vec3<f32>,
-	intensity: f32,
+	corner_radius: f32,
+	material: u32,
+}
+
+struct Ellipse{
+	radii: vec2<f32>,
+	center: vec2<f32>,
+	rotation: f32,
+	inner: f32,
+	material: u32,
+}
+
+struct Polygon{
+	start: u32,
+	len: u32,
+	material: u32,
+}
+
+struct Segment{
+	a: vec2<f32>,
+	b: vec2<f32>,
+	radius: f32,
+	material: u32,
 }
 
 @fragment
-fn fragment(in : FullscreenVertexOutput) -> @location(0) vec4<f32>{
+fn fragment(in : FullscreenVertexOutput) -> SdfOutput {
+	return sdf(in.uv);
+}
 
-	var dist = 1e+10;
+@compute @workgroup_size(8, 8, 1)
+fn compute(@builtin(global_invocation_id) id: vec3<u32>) {
+	let size = textureDimensions(sdf_out);
+	if any(id.xy >= size) {
+		return;
+	}
+
+	let out = sdf((vec2<f32>(id.xy) + .5) / vec2<f32>(size));
+	textureStore(sdf_out, id.xy, out.sdf);
+	textureStore(occluder_out, id.xy, out.occluder);
+	textureStore(absorption_out, id.xy, out.absorption);
+	textureStore(ids_out, id.xy, vec4(out.ids, 0u, 0u));
+}
+
+@fragment
+fn emission(in : FullscreenVertexOutput) -> EmissionOutput {
+	return channel_emission(in.uv);
+}
+
+// closest shape, occluder and light at `uv`, shared by the fragment and compute entry
+fn sdf(uv: vec2<f32>) -> SdfOutput {
+
+	var dist = tile_buffer.max_dist;
+	var occluder = tile_buffer.max_dist;
 	var emit : vec3<f32>;
+	var light_height = in_cfg.light_z;
+	var occluder_height = MAX_HEIGHT;
+	var absorption = vec4(0.);
+	var light_material = NO_SHAPE;
+	var occluder_item = NO_SHAPE;
+	var falloff = vec2(0.);
 
 	let size = vec2<f32>(in_cfg.native);
-	let frag_pos = vec2(size.x * in.uv.x,  size.y - size.y * in.uv.y);
-	let ndc_pos = vec4<f32>((frag_pos.x / size.x) * 2.0 - 1.0,
-                            (frag_pos.y / size.y) * 2.0 - 1.0,
-                            0.0,
-                            1.0);
-
-	let world_position = (view.world_from_clip * ndc_pos ).xy;
-
-	for(var i = 0; i < i32(circle_occluder_buffer.count); i ++ ){
-		let circle = circle_occluder_buffer.data[i];
-		let world_dist = world_circle(
-			circle.center,
-			world_position,
-			circle.radius
-		);
-		emit = select(emit, circle.emit * circle.intensity, (dist > world_dist));
-		dist = min(dist, world_dist);
+	let world_position = world_position(uv);
+
+	let tile_count = tile_buffer.tile_count;
+	let tile = min(vec2<u32>(uv * size) / tile_buffer.tile_size, tile_count - 1u);
+	let tile_index = (tile.y * tile_count.x + tile.x) * 2u;
+	let offset = tile_buffer.data[tile_index];
+	let count = tile_buffer.data[tile_index + 1u];
+
+	// textured lights take their colour from the emission pass
+	let emission_texel = vec2<i32>(uv * vec2<f32>(textureDimensions(emission_tex)));
+	let textured_emit = textureLoad(emission_tex, emission_texel, 0).rgb;
+
+	for(var i = 0u; i < count; i ++ ){
+		let item = tile_buffer.data[offset + i];
+		let hit = shape_distance(item, world_position);
+		let material = material_buffer[hit.material];
+		if dist > hit.dist {
+			emit = select(material.emit * material.intensity, textured_emit, material.textured == 1u);
+			light_height = select(in_cfg.light_z, material.light_height, material.light_height >= 0.);
+			light_material = hit.material;
+			falloff = vec2(material.range, material.falloff);
+			dist = hit.dist;
+		}
+		if (item & SHAPE_OCCLUDER) != 0u && occluder > hit.dist {
+			occluder_height = select(MAX_HEIGHT, material.occluder_height, material.occluder_height >= 0.);
+			absorption = vec4(material.absorption, f32(material.translucent));
+			occluder_item = item;
+			occluder = hit.dist;
+		}
 	}
 
-	for(var i = 0; i < i32(rect_occluder_buffer.count); i ++ ){
-		let rect = rect_occluder_buffer.data[i];
-		let world_dist = world_rect(
-			world_position - rect.center,
-			rect.half_extends,
-			rect.rotation,
-		);
-		emit = select(emit, rect.emit * rect.intensity, (dist > world_dist));
-		dist = min(dist, world_dist);
+	let zoom = in_cfg.zoom;
+	let scale = f32(in_cfg.native.x)/f32(in_cfg.scaled.x);
+
+	// sprite occluders, jump flood distance is in scaled pixel
+	let texel = vec2<i32>(uv * vec2<f32>(textureDimensions(jfa_tex)));
+	let seed = textureLoad(jfa_tex, texel, 0).xy;
+	if seed.x >= 0. {
+		let inside = textureLoad(mask_tex, texel, 0).a > 0.;
+		let pixel_dist = distance(seed, vec2<f32>(texel) + 0.5);
+		let sprite_dist = select(pixel_dist - 0.5, -pixel_dist - 0.5, inside) * scale / zoom;
+		let sprite_emit = textureLoad(mask_tex, vec2<i32>(seed), 0).rgb;
+		emit = select(emit, sprite_emit, (dist > sprite_dist));
+		light_material = select(light_material, SPRITE_SHAPE, (dist > sprite_dist));
+		falloff = select(falloff, vec2(0.), (dist > sprite_dist));
+		dist = min(dist, sprite_dist);
+		occluder_height = select(occluder_height, MAX_HEIGHT, (occluder > sprite_dist));
+		absorption = select(absorption, vec4(0.), (occluder > sprite_dist));
+		occluder_item = select(occluder_item, NO_SHAPE, (occluder > sprite_dist));
+		occluder = min(occluder, sprite_dist);
 	}
 
-	let zoom = get_zoom(view);
+	var out: SdfOutput;
+	out.sdf = vec4(emit, dist / scale) * zoom;
+	let translucent = absorption.a > 0.;
+	out.occluder = vec4(
+		occluder / scale * zoom,
+		select(scaled_height(occluder_height, zoom / scale), -1., translucent),
+		scaled_height(light_height, zoom / scale),
+		falloff.x * zoom / scale,
+	);
+	// positive exponents, negative inverse square radii
+	let falloff_shape = select(falloff.y, falloff.y * zoom / scale, falloff.y < 0.);
+	out.absorption = vec4(absorption.rgb * scale / zoom, falloff_shape);
+	out.ids = vec2(light_material, occluder_item);
+	return out;
+}
+
+// emission of the closest light on the evaluated channel, the distance
+// is kept from the sdf pass
+fn channel_emission(uv: vec2<f32>) -> EmissionOutput {
+	var emit = vec3(0.);
+	var spot = vec4(0., 0., -1., -1.);
+	var cookie = 0u;
+
+	let texel = vec2<i32>(uv * vec2<f32>(textureDimensions(sdf_tex)));
+	let ids = textureLoad(ids_tex, texel, 0).xy;
+
+	if ids.x == SPRITE_SHAPE {
+		let seed = textureLoad(jfa_tex, texel, 0).xy;
+		let sprite = textureLoad(mask_tex, vec2<i32>(seed), 0);
+		emit = sprite.rgb * on_channel(u32(round(sprite.a)) - 1u);
+	} else if ids.x != NO_SHAPE {
+		let material = material_buffer[ids.x];
+		let emission_texel = vec2<i32>(uv * vec2<f32>(textureDimensions(emission_tex)));
+		let textured_emit = textureLoad(emission_tex, emission_texel, 0).rgb;
+		emit = select(material.emit * material.intensity, textured_emit, material.textured == 1u);
+		emit *= on_channel(material.channels);
+		spot = vec4(material.spot_direction, material.spot_inner, material.spot_outer);
+		cookie = material.cookie;
+	}
+
+	let zoom = in_cfg.zoom;
 	let scale = f32(in_cfg.native.x)/f32(in_cfg.scaled.x);
-	return vec4(emit, dist / scale) * zoom;
+
+	// lit occluders reflect last frame's light as emission
+	if ids.y != NO_SHAPE && in_cfg.bounce > 0. {
+		let world_position = world_position(uv);
+		let hit = shape_distance(ids.y, world_position);
+		let albedo = material_buffer[hit.material].albedo;
+		if hit.dist < 0. && any(albedo > vec3(0.)) {
+			let light = surface_light(ids.y, world_position, hit.dist, scale / zoom);
+			emit += albedo * in_cfg.bounce * light / zoom;
+		}
+	}
+
+	var out: EmissionOutput;
+	out.sdf = vec4(emit * zoom, textureLoad(sdf_tex, texel, 0).a);
+	let facing = screen_direction(spot.xy);
+	out.spot = vec4(atan2(facing.y, facing.x), spot.zw, f32(cookie));
+	return out;
 }
 
-//this sucks, precalc
-fn get_zoom(view: View) -> f32 {
-	let scale_x = length(view.view_from_world[0].xyz);
-    let scale_y = length(view.view_from_world[1].xyz);
-    let scale_z = length(view.view_from_world[2].xyz);
+// world position of a fullscreen `uv`
+fn world_position(uv: vec2<f32>) -> vec2<f32> {
+	let ndc_pos = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+	return (view.world_from_clip * ndc_pos).xy;
+}
+
+// last frame's light just outside the closest surface of a shape,
+// `pixel` is the world size of a scaled pixel.
+fn surface_light(item: u32, world_position: vec2<f32>, dist: f32, pixel: f32) -> vec3<f32> {
+	let gradient = vec2(
+		shape_distance(item, world_position + vec2(pixel, 0.)).dist
+			- shape_distance(item, world_position - vec2(pixel, 0.)).dist,
+		shape_distance(item, world_position + vec2(0., pixel)).dist
+			- shape_distance(item, world_position - vec2(0., pixel)).dist,
+	);
+	let normal = gradient / max(length(gradient), EPSILON);
+	let surface = world_position + normal * (f32(in_cfg.probe_base) * pixel - dist);
+
+	let clip = view.clip_from_world * vec4(surface, 0., 1.);
+	let uv = vec2(clip.x / clip.w * .5 + .5, .5 - clip.y / clip.w * .5);
+	let size = vec2<i32>(textureDimensions(bounce_tex));
+	let texel = clamp(vec2<i32>(uv * vec2<f32>(size)), vec2(0), size - 1);
+	return textureLoad(bounce_tex, texel, in_probe.channel, 0).rgb;
+}
+
+// 1 if a light on `channels` shines on the evaluated channel
+fn on_channel(channels: u32) -> f32 {
+	return f32((channels >> in_probe.channel) & 1u);
+}
+
+// world direction to y down screen space, zero stays zero
+fn screen_direction(direction: vec2<f32>) -> vec2<f32> {
+	let view_direction = (view.view_from_world * vec4(direction, 0., 0.)).xy;
+	let len = length(view_direction);
+	return select(vec2(0.), vec2(view_direction.x, -view_direction.y) / len, len > 0.);
+}
+
+// world height to scaled pixel, infinite heights stay at MAX_HEIGHT
+fn scaled_height(height: f32, factor: f32) -> f32 {
+	return select(min(height * factor, MAX_HEIGHT), MAX_HEIGHT, height >= MAX_HEIGHT);
+}
+
+// world distance and material of a binned shape
+fn shape_distance(item: u32, world_position: vec2<f32>) -> ShapeHit {
+	let index = item & SHAPE_INDEX_MASK;
 
-    return (scale_x + scale_y + scale_z) / 3.; // or simply return scale_x/scale_y if needed
+	switch item >> SHAPE_KIND_SHIFT {
+		case SHAPE_CIRCLE: {
+			let circle = circle_occluder_buffer[index];
+			let world_dist = world_ring(
+				circle.center,
+				world_position,
+				circle.radius,
+				circle.inner_radius,
+			);
+			return ShapeHit(world_dist, circle.material);
+		}
+		case SHAPE_RECT: {
+			let rect = rect_occluder_buffer[index];
+			let world_dist = world_rect(
+				world_position - rect.center,
+				rect.half_extends,
+				rect.rotation,
+				rect.corner_radius,
+			);
+			return ShapeHit(world_dist, rect.material);
+		}
+		case SHAPE_POLYGON: {
+			let polygon = polygon_occluder_buffer[index];
+			let world_dist = world_polygon(
+				world_position,
+				polygon.start,
+				polygon.len,
+			);
+			return ShapeHit(world_dist, polygon.material);
+		}
+		case SHAPE_SEGMENT: {
+			let segment = segment_occluder_buffer[index];
+			let world_dist = world_segment(
+				world_position,
+				segment.a,
+				segment.b,
+				segment.radius,
+			);
+			return ShapeHit(world_dist, segment.material);
+		}
+		case SHAPE_ELLIPSE: {
+			let ellipse = ellipse_occluder_buffer[index];
+			var world_dist = world_ellipse(
+				world_position - ellipse.center,
+				ellipse.radii,
+				ellipse.rotation,
+			);
+			if ellipse.inner > 0. {
+				world_dist = max(world_dist, -world_ellipse(
+					world_position - ellipse.center,
+					ellipse.radii * ellipse.inner,
+					ellipse.rotation,
+				));
+			}
+			return ShapeHit(world_dist, ellipse.material);
+		}
+		default: {
+			return ShapeHit(1e+10, 0u);
+		}
+	}
 }
 
 fn world_circle(
@@ -91,21 +390,98 @@ fn world_circle(
 	return length(center - sample) - radius;
 }
 
+fn world_ring(
+	center: vec2<f32>,
+	sample: vec2<f32>,
+	radius: f32,
+	inner_radius: f32,
+) -> f32 {
+	let outer = world_circle(center, sample, radius);
+	let inner = inner_radius - length(center - sample);
+	return select(outer, max(outer, inner), inner_radius > 0.);
+}
+
 fn world_rect(
 	center: vec2<f32>,
 	half_extends: vec2<f32>,
 	angle: f32,
+	corner_radius: f32,
 ) -> f32{
 
+    let edge_distance = abs(center * rotation_matrix(angle)) - half_extends + corner_radius;
+    let outside = length(max(edge_distance, vec2(0.)));
+    let inside = min(max(edge_distance.x, edge_distance.y), 0.);
+    return outside + inside - corner_radius;
+}
+
+// newton iteration on the ellipse angle, good enough after a few steps
+fn world_ellipse(
+	center: vec2<f32>,
+	radii: vec2<f32>,
+	angle: f32,
+) -> f32 {
+	let p = abs(center * rotation_matrix(angle));
+	let q = radii * (p - radii);
+	var w = select(0., PI * 0.5, q.x < q.y);
+
+	for(var i = 0; i < 5; i ++){
+		let cs = vec2(cos(w), sin(w));
+		let u = radii * cs;
+		let v = radii * vec2(-cs.y, cs.x);
+		w = w + dot(p - u, v) / (dot(p - u, u) + dot(v, v));
+	}
+
+	let d = length(p - radii * vec2(cos(w), sin(w)));
+	return select(-d, d, dot(p / radii, p / radii) > 1.);
+}
+
+fn rotation_matrix(angle: f32) -> mat2x2<f32> {
     let cos_angle = cos(angle);
     let sin_angle = sin(angle);
-    let rot_matrix = mat2x2<f32>(
+    return mat2x2<f32>(
         vec2(cos_angle, sin_angle),
         vec2(-sin_angle, cos_angle),
     );
+}
 
-    let edge_distance = abs(center * rot_matrix) - half_extends;
-    let outside = length(max(edge_distance, vec2(0.)));
-    let inside = min(max(edge_distance.x, edge_distance.y), 0.);
-    return outside + inside;
+fn world_segment(
+	sample: vec2<f32>,
+	a: vec2<f32>,
+	b: vec2<f32>,
+	radius: f32,
+) -> f32 {
+	let pa = sample - a;
+	let ba = b - a;
+	let h = clamp(dot(pa, ba) / max(dot(ba, ba), EPSILON), 0., 1.);
+	return length(pa - ba * h) - radius;
+}
+
+// exact signed distance to a simple polygon, works for concave shapes.
+// vertices are expected in world space.
+fn world_polygon(
+	sample: vec2<f32>,
+	start: u32,
+	len: u32,
+) -> f32 {
+	var d = dot(sample - vertex_buffer[start], sample - vertex_buffer[start]);
+	var s = 1.;
+	var j = len - 1u;
+
+	for(var i = 0u; i < len; i ++){
+		let vi = vertex_buffer[start + i];
+		let vj = vertex_buffer[start + j];
+		let e = vj - vi;
+		let w = sample - vi;
+		let b = w - e * clamp(dot(w, e) / dot(e, e), 0., 1.);
+		d = min(d, dot(b, b));
+
+		// even odd crossing test
+		let c = vec3<bool>(sample.y >= vi.y, sample.y < vj.y, e.x * w.y > e.y * w.x);
+		if all(c) || all(!c) {
+			s = -s;
+		}
+		j = i;
+	}
+
+	return s * sqrt(d);
 }
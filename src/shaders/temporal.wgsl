@@ -0,0 +1,34 @@
+#import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
+#import solis_2d::common::{GiConfig, Probe}
+
+@group(0) @binding(0) var light_tex: texture_2d<f32>;
+@group(0) @binding(1) var history_tex: texture_2d_array<f32>;
+@group(0) @binding(2) var sdf_tex: texture_2d<f32>;
+@group(0) @binding(3) var history_sdf_tex: texture_2d<f32>;
+@group(0) @binding(4) var rad_sampler: sampler;
+@group(0) @binding(5) var<uniform> in_cfg: GiConfig;
+@group(0) @binding(6) var<uniform> in_probe: Probe;
+
+// blends this frame's light with the history, reprojected by the camera
+// movement. History off screen or where the scene changed is dropped.
+@fragment
+fn fragment(in : FullscreenVertexOutput) -> @location(0) vec4<f32>{
+	let light = textureSample(light_tex, rad_sampler, in.uv);
+
+	let clip = vec4(in.uv.x * 2. - 1., 1. - in.uv.y * 2., 0., 1.);
+	let history_clip = in_cfg.reprojection * clip;
+	let history_uv = vec2(history_clip.x, -history_clip.y) / history_clip.w * .5 + .5;
+
+	let sdf = textureSample(sdf_tex, rad_sampler, in.uv).a;
+	let history_sdf = textureSample(history_sdf_tex, rad_sampler, history_uv).a;
+	let history = textureSample(history_tex, rad_sampler, history_uv, in_probe.channel);
+
+	let valid = ((in_cfg.history_channels >> in_probe.channel) & 1u) != 0u;
+	let on_screen = all(history_uv >= vec2(0.)) && all(history_uv <= vec2(1.));
+	let disoccluded = abs(sdf - history_sdf) > in_cfg.disocclusion_threshold;
+	if !valid || !on_screen || disoccluded {
+		return light;
+	}
+
+	return mix(light, history, in_cfg.temporal_blend);
+}
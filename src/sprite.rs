@@ -0,0 +1,162 @@
+use bevy::{
+    prelude::*,
+    render::{
+        render_asset::RenderAssets,
+        render_resource::{
+            BindGroup, BindGroupEntries, BindGroupLayout, DynamicUniformBuffer, ShaderType,
+        },
+        renderer::{RenderDevice, RenderQueue},
+        texture::GpuImage,
+        view::RenderLayers,
+    },
+};
+use std::ops::DerefMut;
+
+/// A sprite texture drawn as a quad, shared by the sprite occluder mask,
+/// textured lights and the lighting mask.
+#[derive(ShaderType, Debug, Clone)]
+pub struct GpuSpriteQuad {
+    /// maps the unit quad to world space
+    pub(crate) world_from_local: Mat4,
+    pub(crate) uv_min: Vec2,
+    pub(crate) uv_max: Vec2,
+    pub(crate) alpha_threshold: f32,
+    pub(crate) emit: Vec3,
+    /// light channels the sprite shines on or is lit by
+    pub(crate) channels: u32,
+}
+
+/// placement of a sprite texture in the world
+pub(crate) struct SpriteQuad {
+    transform: Mat4,
+    custom_size: Option<Vec2>,
+    rect: Option<Rect>,
+    anchor: Vec2,
+    flip_x: bool,
+    flip_y: bool,
+}
+
+impl SpriteQuad {
+    pub(crate) fn new(sprite: &Sprite, global: &GlobalTransform) -> Self {
+        Self {
+            transform: global.compute_matrix(),
+            custom_size: sprite.custom_size,
+            rect: sprite.rect,
+            anchor: sprite.anchor.as_vec(),
+            flip_x: sprite.flip_x,
+            flip_y: sprite.flip_y,
+        }
+    }
+
+    /// unit quad to world transform and uv range, drawn like bevy's sprites
+    pub(crate) fn layout(&self, image: &GpuImage) -> (Mat4, Vec2, Vec2) {
+        let image_size = image.size.as_vec2();
+        let rect = self
+            .rect
+            .unwrap_or(Rect::from_corners(Vec2::ZERO, image_size));
+        let size = self.custom_size.unwrap_or(rect.size());
+
+        let mut uv_min = rect.min / image_size;
+        let mut uv_max = rect.max / image_size;
+        if self.flip_x {
+            std::mem::swap(&mut uv_min.x, &mut uv_max.x);
+        }
+        if self.flip_y {
+            std::mem::swap(&mut uv_min.y, &mut uv_max.y);
+        }
+
+        let world_from_local = self.transform
+            * Mat4::from_translation((-self.anchor * size).extend(0.))
+            * Mat4::from_scale(size.extend(1.));
+
+        (world_from_local, uv_min, uv_max)
+    }
+}
+
+pub struct ExtractedSpriteQuad {
+    pub(crate) image: AssetId<Image>,
+    pub(crate) quad: SpriteQuad,
+    pub(crate) alpha_threshold: f32,
+    pub(crate) emit: Vec3,
+    pub(crate) channels: u32,
+    pub(crate) layers: RenderLayers,
+}
+
+/// the sprite quads of one pass
+#[derive(Default)]
+pub struct SpriteQuadBuffers {
+    pub extracted: Vec<ExtractedSpriteQuad>,
+    pub uniforms: DynamicUniformBuffer<GpuSpriteQuad>,
+    /// image, uniform offset and render layers of each prepared sprite
+    pub sprites: Vec<(AssetId<Image>, u32, RenderLayers)>,
+}
+
+impl SpriteQuadBuffers {
+    /// a bind group and uniform offset for each sprite on the view's layers
+    pub(crate) fn bind_groups(
+        &self,
+        label: &'static str,
+        render_device: &RenderDevice,
+        layout: &BindGroupLayout,
+        gpu_images: &RenderAssets<GpuImage>,
+        view_layers: &RenderLayers,
+    ) -> Vec<(BindGroup, u32)> {
+        let Some(binding) = self.uniforms.binding() else {
+            return Vec::new();
+        };
+
+        self.sprites
+            .iter()
+            .filter(|(_, _, layers)| layers.intersects(view_layers))
+            .filter_map(|(image, offset, _)| {
+                let image = gpu_images.get(*image)?;
+                let bind_group = render_device.create_bind_group(
+                    Some(label),
+                    layout,
+                    &BindGroupEntries::sequential((
+                        &image.texture_view,
+                        &image.sampler,
+                        binding.clone(),
+                    )),
+                );
+                Some((bind_group, *offset))
+            })
+            .collect()
+    }
+}
+
+/// writes the uniforms of the extracted sprites whose image is loaded
+pub(crate) fn prepare_sprite_quads<T: Resource + DerefMut<Target = SpriteQuadBuffers>>(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    gpu_images: Res<RenderAssets<GpuImage>>,
+    mut buffers: ResMut<T>,
+) {
+    let SpriteQuadBuffers {
+        extracted,
+        uniforms,
+        sprites,
+    } = buffers.as_mut().deref_mut();
+
+    uniforms.clear();
+    sprites.clear();
+
+    for sprite in extracted.iter() {
+        let Some(image) = gpu_images.get(sprite.image) else {
+            continue;
+        };
+
+        let (world_from_local, uv_min, uv_max) = sprite.quad.layout(image);
+        let offset = uniforms.push(&GpuSpriteQuad {
+            world_from_local,
+            uv_min,
+            uv_max,
+            alpha_threshold: sprite.alpha_threshold,
+            emit: sprite.emit,
+            channels: sprite.channels,
+        });
+        sprites.push((sprite.image, offset, sprite.layers.clone()));
+    }
+
+    uniforms.write_buffer(&render_device, &render_queue);
+}
@@ -0,0 +1,117 @@
+use crate::receiver::{light_channels, LightChannels};
+use bevy::{
+    prelude::*,
+    render::{
+        render_resource::ShaderType,
+        view::{ExtractedView, RenderLayers},
+        Extract,
+    },
+};
+
+/// upper limit of directional lights a view sees at once
+pub(crate) const MAX_DIRECTIONAL_LIGHTS: usize = 4;
+
+/// A far away light like the sun or moon, casting parallel shadows
+/// across the whole scene. Rays heading towards it that escape
+/// without a hit pick up its light.
+#[derive(Component, Clone)]
+pub struct DirectionalLight2d {
+    /// world space direction the light travels in
+    pub direction: Vec2,
+    pub color: Color,
+    /// light an unshadowed spot receives, independent of `angular_size`
+    pub intensity: f32,
+    /// apparent size in radians, larger suns cast softer shadows
+    pub angular_size: f32,
+}
+
+impl Default for DirectionalLight2d {
+    fn default() -> Self {
+        Self {
+            direction: Vec2::NEG_Y,
+            color: Color::WHITE,
+            intensity: 1.,
+            angular_size: 0.05,
+        }
+    }
+}
+
+#[derive(ShaderType, Clone, Copy, Default)]
+pub struct GpuDirectionalLight {
+    color: Vec4,
+    /// screen space direction the light travels in, y down
+    direction: Vec2,
+    angular_size: f32,
+    /// light channels the light shines on
+    channels: u32,
+}
+
+pub(crate) struct ExtractedDirectionalLight {
+    color: Vec4,
+    direction: Vec2,
+    angular_size: f32,
+    channels: u32,
+    layers: RenderLayers,
+}
+
+#[derive(Resource, Default)]
+pub(crate) struct ExtractedDirectionalLights(Vec<ExtractedDirectionalLight>);
+
+type DirectionalLightItem = (
+    &'static DirectionalLight2d,
+    Option<&'static RenderLayers>,
+    Option<&'static LightChannels>,
+);
+
+pub(crate) fn extract_directional_lights(
+    lights: Extract<Query<DirectionalLightItem>>,
+    mut extracted: ResMut<ExtractedDirectionalLights>,
+) {
+    extracted.0.clear();
+    extracted.0.extend(
+        lights
+            .iter()
+            .map(|(light, layers, channels)| ExtractedDirectionalLight {
+                color: (light.color.to_linear().to_vec3() * light.intensity).extend(1.),
+                direction: light.direction.normalize_or_zero(),
+                angular_size: light.angular_size.max(0.),
+                channels: light_channels(channels),
+                layers: layers.cloned().unwrap_or_default(),
+            }),
+    );
+}
+
+impl ExtractedDirectionalLights {
+    /// lights sharing a layer with the view, directions in screen space
+    pub(crate) fn for_view(
+        &self,
+        view: &ExtractedView,
+        view_layers: &RenderLayers,
+    ) -> ([GpuDirectionalLight; MAX_DIRECTIONAL_LIGHTS], u32) {
+        let view_from_world = view.world_from_view.compute_matrix().inverse();
+        let mut lights = [GpuDirectionalLight::default(); MAX_DIRECTIONAL_LIGHTS];
+        let mut count = 0;
+
+        for light in self.0.iter().filter(|l| l.layers.intersects(view_layers)) {
+            if count == MAX_DIRECTIONAL_LIGHTS {
+                warn_once!("more than {MAX_DIRECTIONAL_LIGHTS} directional lights in a view");
+                break;
+            }
+
+            let direction = view_from_world
+                .transform_vector3(light.direction.extend(0.))
+                .truncate()
+                .normalize_or_zero();
+
+            lights[count] = GpuDirectionalLight {
+                color: light.color,
+                direction: Vec2::new(direction.x, -direction.y),
+                angular_size: light.angular_size,
+                channels: light.channels,
+            };
+            count += 1;
+        }
+
+        (lights, count as u32)
+    }
+}
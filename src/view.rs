@@ -1,15 +1,28 @@
-use crate::{constant::CASCADE_FORMAT, radiance::Probe};
+use crate::{
+    compute::RadianceComputePipeline,
+    constant::{
+        ABSORPTION_FORMAT, CASCADE_FORMAT, JFA_FORMAT, LIGHTING_MASK_FORMAT, OCCLUDER_FORMAT,
+        SDF_FORMAT, SHAPE_ID_FORMAT, SPOT_FORMAT,
+    },
+    jfa::JfaStep,
+    layers::LayerCameras,
+    radiance::Probe,
+    receiver::{ActiveLightChannels, MAX_LIGHT_CHANNELS},
+    sun::{ExtractedDirectionalLights, GpuDirectionalLight, MAX_DIRECTIONAL_LIGHTS},
+};
 use bevy::{
+    ecs::entity::EntityHashMap,
     prelude::*,
     render::{
         extract_component::ExtractComponent,
         render_resource::{
             DynamicUniformBuffer, Extent3d, ShaderType, TextureDescriptor, TextureDimension,
-            TextureUsages, UniformBuffer,
+            TextureFormat, TextureUsages, TextureViewDescriptor, TextureViewDimension,
+            UniformBuffer,
         },
         renderer::{RenderDevice, RenderQueue},
         texture::{CachedTexture, TextureCache},
-        view::ViewTarget,
+        view::{ExtractedView, RenderLayers, ViewTarget},
     },
 };
 
@@ -27,16 +40,74 @@ pub struct RadianceConfig {
     pub probe_base: u32,
     /// highlighting edges
     pub edge_hightlight: f32,
-    /// light z pos
+    /// height of lights without their own, in world units
     pub light_z: f32,
+    /// how much of last frame's light an `Albedo` occluder reflects,
+    /// every frame adds one more bounce. `0.` disables bouncing.
+    pub bounce: f32,
     /// absorb color is subtracted from the scene
     pub absorb: LinearRgba,
     /// final color multiplier
     pub modulate: LinearRgba,
+    /// light of rays escaping the scene
+    pub environment: Environment,
+    /// how cascades merge, trading cost for clean shadow edges
+    pub quality: RadianceQuality,
+    /// blends the light with previous frames to calm shimmer,
+    /// off if `None`
+    pub temporal: Option<TemporalFilter>,
     /// config flags `GiFlags`
     pub flags: GiFlags,
 }
 
+/// Sky light of rays that leave the screen or reach the top cascade
+/// without a hit. Open areas are lit softly, occluders cast ambient shadows.
+#[derive(Clone, Default)]
+pub enum Environment {
+    #[default]
+    None,
+    /// the same light from every direction
+    Color(LinearRgba),
+    /// blends from `down` to `up` by the screen space ray direction
+    Gradient { up: LinearRgba, down: LinearRgba },
+    /// 1D texture indexed by ray angle, counter clockwise from +x
+    Texture(Handle<Image>),
+}
+
+/// How a ray picks up the light of the cascade above it.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum RadianceQuality {
+    /// one bilinear sample of the upper probes, rings and leaks
+    /// light past occluders at a low `probe_base`
+    #[default]
+    Fast,
+    /// casts a ray to each of the four closest upper probes and
+    /// weights them bilinearly, four times the cost of `Fast`
+    BilinearFix,
+}
+
+/// Blends each frame's light with the previous ones, reprojected by the
+/// camera movement. Moving lights and cameras shimmer less but their
+/// light trails a little.
+#[derive(Clone, Copy, Debug)]
+pub struct TemporalFilter {
+    /// share of the history kept each frame, higher is calmer
+    /// but slower to follow changes
+    pub blend: f32,
+    /// change of the scene distance in scaled pixel past which the
+    /// history is dropped, where occluders moved in or out of view
+    pub disocclusion_threshold: f32,
+}
+
+impl Default for TemporalFilter {
+    fn default() -> Self {
+        Self {
+            blend: 0.8,
+            disocclusion_threshold: 2.,
+        }
+    }
+}
+
 #[derive(Component, Default, Clone, ExtractComponent)]
 pub struct DisableLight;
 
@@ -49,8 +120,12 @@ impl Default for RadianceConfig {
             probe_base: 1,
             edge_hightlight: 1.,
             light_z: 5.,
+            bounce: 1.,
             modulate: LinearRgba::WHITE,
             absorb: LinearRgba::BLACK,
+            environment: Environment::None,
+            quality: RadianceQuality::Fast,
+            temporal: None,
             flags: GiFlags::DEFAULT,
         }
     }
@@ -70,89 +145,252 @@ pub struct GiGpuConfig {
     flags: u32,
     edge_hightlight: f32,
     light_z: f32,
+    layer_threshold: f32,
+    /// world to view scale, distances and heights are stored zoomed
+    zoom: f32,
+    bounce: f32,
+    /// 1 if the environment is sampled from a texture
+    sky_texture: u32,
+    /// light channels evaluated this frame
+    channels: u32,
+    /// 1 to merge with a ray per upper probe
+    bilinear_fix: u32,
     absorb: LinearRgba,
     modulate: LinearRgba,
+    sky_up: LinearRgba,
+    sky_down: LinearRgba,
+    sun_count: u32,
+    suns: [GpuDirectionalLight; MAX_DIRECTIONAL_LIGHTS],
+    /// this frame's clip space to the history's
+    reprojection: Mat4,
+    /// share of the history kept, 0 without a temporal filter
+    temporal_blend: f32,
+    disocclusion_threshold: f32,
+    /// channels with a history to blend with
+    history_channels: u32,
 }
 
 #[derive(Component, Default)]
 pub struct RadianceBuffers {
     pub config_buffer: UniformBuffer<GiGpuConfig>,
     pub probe_buffer: DynamicUniformBuffer<Probe>,
-    pub probe_offsets: Vec<u32>,
+    /// evaluated light channels and their probe offset per cascade
+    pub probe_offsets: Vec<(u32, Vec<u32>)>,
+    /// probes per side of a cascade, including all directions
+    pub cascade_size: UVec2,
+    pub jfa_buffer: DynamicUniformBuffer<JfaStep>,
+    pub jfa_offsets: Vec<u32>,
 }
 
 #[derive(Component)]
 pub struct RadianceTargets {
+    /// distance to the closest shape, built once per view
     pub sdf: CachedTexture,
+    /// the sdf with the emission of the evaluated light channel
+    pub channel_sdf: CachedTexture,
+    pub shape_ids: CachedTexture,
+    pub occluder: CachedTexture,
+    pub absorption: CachedTexture,
+    pub spot: CachedTexture,
+    pub mask: CachedTexture,
+    pub lighting_mask: CachedTexture,
+    pub emission: CachedTexture,
+    pub jfa0: CachedTexture,
+    pub jfa1: CachedTexture,
     pub merge0: CachedTexture,
     pub merge1: CachedTexture,
     pub mipmap: CachedTexture,
+    /// this frame's light blended with the history
+    pub temporal: CachedTexture,
     pub fallback: CachedTexture,
 }
 
+/// light of a view, one layer per light channel. The composite reads
+/// this frame's light, the next frame bounces it off occluders and
+/// blends it into its own.
+#[derive(Component, Clone)]
+pub struct RadianceHistory {
+    pub light: CachedTexture,
+    /// last frame's scene distance, rejects history where it changed
+    pub sdf: CachedTexture,
+    /// this frame's clip space to last frame's
+    pub reprojection: Mat4,
+    /// channels whose layer holds last frame's light
+    pub valid_channels: u32,
+    clip_from_world: Mat4,
+    channels: u32,
+}
+
+impl RadianceHistory {
+    fn new(render_device: &RenderDevice, light_size: Extent3d, sdf_size: Extent3d) -> Self {
+        Self {
+            light: new_history_texture(
+                render_device,
+                light_size,
+                CASCADE_FORMAT,
+                TextureViewDimension::D2Array,
+            ),
+            sdf: new_history_texture(
+                render_device,
+                sdf_size,
+                SDF_FORMAT,
+                TextureViewDimension::D2,
+            ),
+            reprojection: Mat4::IDENTITY,
+            valid_channels: 0,
+            clip_from_world: Mat4::IDENTITY,
+            channels: 0,
+        }
+    }
+}
+
+/// render world views are spawned every frame,
+/// their history is kept by view entity.
+#[derive(Resource, Default)]
+pub struct RadianceHistories(EntityHashMap<RadianceHistory>);
+
 #[derive(Component, ExtractComponent, Clone, Default, Deref, DerefMut)]
 pub struct NormalTarget(pub Handle<Image>);
 
+type ConfigViewItem = (
+    Entity,
+    &'static ExtractedView,
+    &'static ViewTarget,
+    &'static RadianceConfig,
+    Option<&'static LayerCameras>,
+    Option<&'static RenderLayers>,
+);
+
 pub(crate) fn prepare_config(
-    views: Query<(Entity, &ViewTarget, &RadianceConfig)>,
+    views: Query<ConfigViewItem>,
+    directional_lights: Res<ExtractedDirectionalLights>,
+    active_channels: Res<ActiveLightChannels>,
+    histories: Res<RadianceHistories>,
     render_device: Res<RenderDevice>,
     render_queue: Res<RenderQueue>,
     mut cmd: Commands,
 ) {
-    views.iter().for_each(|(entity, view_target, cfg)| {
-        let target_size = view_target.main_texture().size();
-        let native = Vec2::new(target_size.width as f32, target_size.height as f32);
-        let scaled = native / cfg.scale_factor;
-
-        let mut config_buffer = UniformBuffer::<GiGpuConfig>::default();
-        let config = config_buffer.get_mut();
-        config.native = native.as_uvec2();
-        config.scaled = scaled.as_uvec2();
-        config.cascade_count = cfg.cascade_count;
-        config.scale = cfg.scale_factor;
-        config.flags = cfg.flags.bits();
-        config.probe_base = cfg.probe_base;
-        config.interval = cfg.interval;
-        config.edge_hightlight = cfg.edge_hightlight;
-        config.light_z = cfg.light_z;
-        config.modulate = cfg.modulate;
-        config.absorb = cfg.absorb;
-        config_buffer.write_buffer(&render_device, &render_queue);
-
-        let mut probe_buffer = DynamicUniformBuffer::default();
-        let mut probe_offsets = vec![];
-        for c in 0..cfg.cascade_count {
-            let index = cfg.cascade_count - 1 - c;
-            let probe = Probe {
-                cascade_index: index,
+    views
+        .iter()
+        .for_each(|(entity, view, view_target, cfg, layers, view_layers)| {
+            let target_size = view_target.main_texture().size();
+            let native = Vec2::new(target_size.width as f32, target_size.height as f32);
+            let scaled = native / cfg.scale_factor;
+
+            let mut config_buffer = UniformBuffer::<GiGpuConfig>::default();
+            let config = config_buffer.get_mut();
+            config.native = native.as_uvec2();
+            config.scaled = scaled.as_uvec2();
+            config.cascade_count = cfg.cascade_count;
+            config.scale = cfg.scale_factor;
+            config.flags = cfg.flags.bits();
+            config.probe_base = cfg.probe_base;
+            config.interval = cfg.interval;
+            config.edge_hightlight = cfg.edge_hightlight;
+            config.light_z = cfg.light_z;
+            config.layer_threshold = layers.map(|l| l.alpha_threshold).unwrap_or_default();
+            config.zoom = view_zoom(view);
+            config.bounce = cfg.bounce;
+            (config.suns, config.sun_count) =
+                directional_lights.for_view(view, &view_layers.cloned().unwrap_or_default());
+            config.channels = active_channels.0;
+            config.bilinear_fix = (cfg.quality == RadianceQuality::BilinearFix) as u32;
+            if let (Some(temporal), Some(history)) = (cfg.temporal, histories.0.get(&entity)) {
+                config.reprojection = history.reprojection;
+                config.temporal_blend = temporal.blend.clamp(0., 1.);
+                config.disocclusion_threshold = temporal.disocclusion_threshold.max(0.);
+                config.history_channels = history.valid_channels;
+            }
+            config.modulate = cfg.modulate;
+            config.absorb = cfg.absorb;
+            (config.sky_up, config.sky_down, config.sky_texture) = match &cfg.environment {
+                Environment::None => (LinearRgba::BLACK, LinearRgba::BLACK, 0),
+                Environment::Color(color) => (*color, *color, 0),
+                Environment::Gradient { up, down } => (*up, *down, 0),
+                Environment::Texture(_) => (LinearRgba::BLACK, LinearRgba::BLACK, 1),
             };
-            probe_offsets.push(probe_buffer.push(&probe));
-        }
+            config_buffer.write_buffer(&render_device, &render_queue);
+
+            let mut probe_buffer = DynamicUniformBuffer::default();
+            let mut probe_offsets = vec![];
+            for channel in 0..MAX_LIGHT_CHANNELS {
+                if active_channels.0 & 1 << channel == 0 {
+                    continue;
+                }
+
+                let mut offsets = vec![];
+                for c in 0..cfg.cascade_count {
+                    let index = cfg.cascade_count - 1 - c;
+                    let probe = Probe {
+                        cascade_index: index,
+                        channel,
+                    };
+                    offsets.push(probe_buffer.push(&probe));
+                }
+                probe_offsets.push((channel, offsets));
+            }
+
+            probe_buffer.write_buffer(&render_device, &render_queue);
 
-        probe_buffer.write_buffer(&render_device, &render_queue);
-        cmd.entity(entity).insert(RadianceBuffers {
-            config_buffer,
-            probe_buffer,
-            probe_offsets,
+            // jump flood steps, halving from the largest power of two
+            let mut jfa_buffer = DynamicUniformBuffer::default();
+            let mut jfa_offsets = vec![];
+            let max_side = scaled.max_element().max(1.) as u32;
+            let mut step = max_side.next_power_of_two() / 2;
+            while step > 0 {
+                jfa_offsets.push(jfa_buffer.push(&JfaStep { step }));
+                step /= 2;
+            }
+
+            jfa_buffer.write_buffer(&render_device, &render_queue);
+            cmd.entity(entity).insert(RadianceBuffers {
+                config_buffer,
+                probe_buffer,
+                probe_offsets,
+                cascade_size: scaled.as_uvec2() / cfg.probe_base,
+                jfa_buffer,
+                jfa_offsets,
+            });
         });
-    });
+}
+
+/// average scale of the view, one world unit covers `zoom` pixel
+pub(crate) fn view_zoom(view: &ExtractedView) -> f32 {
+    let view_from_world = view.world_from_view.compute_matrix().inverse();
+    let zoom = (view_from_world.x_axis.truncate().length()
+        + view_from_world.y_axis.truncate().length()
+        + view_from_world.z_axis.truncate().length())
+        / 3.;
+    zoom.max(f32::EPSILON)
 }
 
 pub(crate) fn prepare_textures(
-    views: Query<(Entity, &ViewTarget, &RadianceConfig)>,
+    views: Query<(Entity, &ExtractedView, &ViewTarget, &RadianceConfig)>,
+    active_channels: Res<ActiveLightChannels>,
+    compute: Option<Res<RadianceComputePipeline>>,
     render_device: Res<RenderDevice>,
     mut texture_cache: ResMut<TextureCache>,
+    mut histories: ResMut<RadianceHistories>,
     mut cmd: Commands,
 ) {
-    views.iter().for_each(|(entity, view_target, cfg)| {
-        let mut scaled_size = view_target.main_texture().size();
-        scaled_size.depth_or_array_layers = 1;
+    histories.0.retain(|entity, _| views.contains(*entity));
+
+    // compute passes write the sdf and cascades as storage textures
+    let storage = match compute {
+        Some(_) => TextureUsages::STORAGE_BINDING,
+        None => TextureUsages::empty(),
+    };
+
+    views.iter().for_each(|(entity, view, view_target, cfg)| {
+        let mut native_size = view_target.main_texture().size();
+        native_size.depth_or_array_layers = 1;
+        let mut scaled_size = native_size;
         scaled_size.width = (scaled_size.width as f32 / cfg.scale_factor) as u32;
         scaled_size.height = (scaled_size.height as f32 / cfg.scale_factor) as u32;
         scaled_size.width += scaled_size.width % 2;
         scaled_size.height += scaled_size.height % 2;
 
-        let mut new_texture = |extent: Extent3d| {
+        let mut new_texture = |extent: Extent3d, format: TextureFormat, usage: TextureUsages| {
             texture_cache.get(
                 &render_device,
                 TextureDescriptor {
@@ -161,39 +399,118 @@ pub(crate) fn prepare_textures(
                     mip_level_count: 1,
                     sample_count: 1,
                     dimension: TextureDimension::D2,
-                    format: CASCADE_FORMAT,
-                    usage: TextureUsages::TEXTURE_BINDING | TextureUsages::RENDER_ATTACHMENT,
+                    format,
+                    usage: TextureUsages::TEXTURE_BINDING
+                        | TextureUsages::RENDER_ATTACHMENT
+                        | TextureUsages::COPY_SRC
+                        | usage,
                     view_formats: &[],
                 },
             )
         };
 
-        let merge0 = new_texture(scaled_size);
-        let merge1 = new_texture(scaled_size);
-        let sdf = new_texture(scaled_size);
+        let merge0 = new_texture(scaled_size, CASCADE_FORMAT, storage);
+        let merge1 = new_texture(scaled_size, CASCADE_FORMAT, storage);
+        let sdf = new_texture(scaled_size, SDF_FORMAT, storage);
+        let channel_sdf = new_texture(scaled_size, SDF_FORMAT, TextureUsages::empty());
+        let shape_ids = new_texture(scaled_size, SHAPE_ID_FORMAT, storage);
+        let occluder = new_texture(scaled_size, OCCLUDER_FORMAT, storage);
+        let absorption = new_texture(scaled_size, ABSORPTION_FORMAT, storage);
+        let spot = new_texture(scaled_size, SPOT_FORMAT, TextureUsages::empty());
+        let mask = new_texture(scaled_size, SDF_FORMAT, TextureUsages::empty());
+        let emission = new_texture(scaled_size, SDF_FORMAT, TextureUsages::empty());
+        let lighting_mask = new_texture(native_size, LIGHTING_MASK_FORMAT, TextureUsages::empty());
+        let jfa0 = new_texture(scaled_size, JFA_FORMAT, TextureUsages::empty());
+        let jfa1 = new_texture(scaled_size, JFA_FORMAT, TextureUsages::empty());
         let mipmap_size = Extent3d {
             width: (scaled_size.width + cfg.probe_base + 1) / cfg.probe_base,
             height: (scaled_size.height + cfg.probe_base + 1) / cfg.probe_base,
             depth_or_array_layers: 1,
         };
 
-        let mipmap = new_texture(mipmap_size);
-        let fallback = new_texture(Extent3d {
-            width: 1,
-            height: 1,
-            ..default()
+        let mipmap = new_texture(mipmap_size, CASCADE_FORMAT, TextureUsages::empty());
+        let temporal = new_texture(mipmap_size, CASCADE_FORMAT, TextureUsages::empty());
+        let fallback = new_texture(
+            Extent3d {
+                width: 1,
+                height: 1,
+                ..default()
+            },
+            CASCADE_FORMAT,
+            TextureUsages::empty(),
+        );
+
+        let history_size = Extent3d {
+            depth_or_array_layers: MAX_LIGHT_CHANNELS,
+            ..mipmap_size
+        };
+        let history = histories
+            .0
+            .entry(entity)
+            .or_insert_with(|| RadianceHistory::new(&render_device, history_size, scaled_size));
+        if history.light.texture.size() != history_size || history.sdf.texture.size() != scaled_size
+        {
+            *history = RadianceHistory::new(&render_device, history_size, scaled_size);
+        }
+
+        // reprojection of this frame, the history keeps the camera for the next
+        let clip_from_world = view.clip_from_world.unwrap_or_else(|| {
+            view.clip_from_view * view.world_from_view.compute_matrix().inverse()
         });
+        history.reprojection = history.clip_from_world * clip_from_world.inverse();
+        history.valid_channels = history.channels & active_channels.0;
+        history.clip_from_world = clip_from_world;
+        history.channels = active_channels.0;
+        cmd.entity(entity).insert(history.clone());
 
         cmd.entity(entity).insert(RadianceTargets {
             merge0,
             merge1,
             sdf,
+            channel_sdf,
+            shape_ids,
+            occluder,
+            absorption,
+            spot,
+            mask,
+            lighting_mask,
+            emission,
+            jfa0,
+            jfa1,
             mipmap,
+            temporal,
             fallback,
         });
     });
 }
 
+/// history textures outlive the texture cache, which
+/// hands out recycled textures every frame.
+fn new_history_texture(
+    render_device: &RenderDevice,
+    size: Extent3d,
+    format: TextureFormat,
+    dimension: TextureViewDimension,
+) -> CachedTexture {
+    let texture = render_device.create_texture(&TextureDescriptor {
+        label: Some("radiance_history_texture"),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format,
+        usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+        view_formats: &[],
+    });
+    CachedTexture {
+        default_view: texture.create_view(&TextureViewDescriptor {
+            dimension: Some(dimension),
+            ..default()
+        }),
+        texture,
+    }
+}
+
 bitflags::bitflags! {
     #[derive(Clone, Default, Copy, Debug, PartialEq, Eq, Hash)]
     #[repr(transparent)]